@@ -0,0 +1,12 @@
+CREATE TABLE sessions (
+    id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    session VARCHAR(128) NOT NULL,
+    user_id INT NOT NULL,
+    admin TINYINT NOT NULL DEFAULT 0,
+    created BIGINT NOT NULL,
+    last_seen BIGINT NOT NULL,
+    user_agent VARCHAR(512) NOT NULL DEFAULT '',
+    ip VARCHAR(64) NOT NULL DEFAULT '',
+    INDEX (session),
+    INDEX (user_id)
+);
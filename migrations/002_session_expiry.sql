@@ -0,0 +1,4 @@
+ALTER TABLE sessions
+    ADD COLUMN remember TINYINT NOT NULL DEFAULT 0,
+    ADD COLUMN expires BIGINT NOT NULL DEFAULT 0,
+    ADD COLUMN max_expires BIGINT NOT NULL DEFAULT 0;
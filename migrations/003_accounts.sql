@@ -0,0 +1,24 @@
+CREATE TABLE accounts (
+    id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    email VARCHAR(64) NOT NULL UNIQUE,
+    role VARCHAR(16) NOT NULL DEFAULT 'user',
+    subscription_policy INT NOT NULL DEFAULT 1,
+    password VARCHAR(256) NOT NULL DEFAULT '',
+    refresh_token VARCHAR(512) NOT NULL DEFAULT ''
+);
+
+INSERT INTO accounts (email, role, subscription_policy, password, refresh_token)
+SELECT LOWER(email), 'admin', subscription_policy, COALESCE(password, ''), COALESCE(refresh_token, '')
+FROM admin;
+
+INSERT INTO accounts (email, role, subscription_policy)
+SELECT LOWER(users.email), 'user', users.subscription_policy
+FROM users
+WHERE LOWER(users.email) NOT IN (SELECT email FROM accounts);
+
+-- Session rows refer to the old per-table ids, so every tracked session is ended.
+DELETE FROM sessions;
+ALTER TABLE sessions DROP COLUMN admin;
+
+DROP TABLE users;
+DROP TABLE admin;
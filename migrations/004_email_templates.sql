@@ -0,0 +1,7 @@
+CREATE TABLE email_templates (
+    id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    name VARCHAR(64) NOT NULL UNIQUE,
+    subject VARCHAR(256) NOT NULL DEFAULT '',
+    text TEXT NOT NULL,
+    html TEXT NOT NULL
+);
@@ -0,0 +1,13 @@
+CREATE TABLE outbox (
+    id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    recipients TEXT NOT NULL,
+    subject VARCHAR(256) NOT NULL DEFAULT '',
+    email LONGTEXT NOT NULL,
+    status VARCHAR(16) NOT NULL DEFAULT 'pending',
+    attempts INT NOT NULL DEFAULT 0,
+    next_attempt BIGINT NOT NULL DEFAULT 0,
+    last_error TEXT NOT NULL,
+    created BIGINT NOT NULL,
+    sent BIGINT NOT NULL DEFAULT 0,
+    INDEX (status)
+);
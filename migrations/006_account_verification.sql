@@ -0,0 +1,4 @@
+ALTER TABLE accounts ADD COLUMN verified TINYINT NOT NULL DEFAULT 0;
+
+-- Accounts created before verification was recorded are assumed to be verified.
+UPDATE accounts SET verified = 1;
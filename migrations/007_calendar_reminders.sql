@@ -0,0 +1,7 @@
+CREATE TABLE calendar_reminders (
+    id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    event_id INT NOT NULL,
+    date VARCHAR(10) NOT NULL,
+    sent BIGINT NOT NULL,
+    INDEX (event_id)
+);
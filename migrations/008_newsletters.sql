@@ -0,0 +1,14 @@
+CREATE TABLE newsletters (
+    id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    subject VARCHAR(256) NOT NULL DEFAULT '',
+    recipients VARCHAR(64) NOT NULL DEFAULT '',
+    message LONGTEXT NOT NULL,
+    status VARCHAR(16) NOT NULL DEFAULT 'draft',
+    send_at BIGINT NOT NULL DEFAULT 0,
+    created BIGINT NOT NULL,
+    updated BIGINT NOT NULL,
+    sent BIGINT NOT NULL DEFAULT 0,
+    count INT NOT NULL DEFAULT 0,
+    last_error TEXT NOT NULL,
+    INDEX (status)
+);
@@ -0,0 +1,25 @@
+CREATE TABLE campaigns (
+    id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    subject VARCHAR(256) NOT NULL DEFAULT '',
+    body LONGTEXT NOT NULL,
+    html LONGTEXT NOT NULL,
+    sender VARCHAR(64) NOT NULL DEFAULT '',
+    audience VARCHAR(64) NOT NULL DEFAULT '',
+    delivery VARCHAR(16) NOT NULL DEFAULT 'individual',
+    track_opens TINYINT NOT NULL DEFAULT 0,
+    created BIGINT NOT NULL,
+    count INT NOT NULL DEFAULT 0
+);
+
+CREATE TABLE campaign_recipients (
+    id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    campaign_id INT NOT NULL,
+    account_id INT NOT NULL DEFAULT 0,
+    email VARCHAR(64) NOT NULL,
+    outbox_id INT NOT NULL DEFAULT 0,
+    opened BIGINT NOT NULL DEFAULT 0,
+    INDEX (campaign_id)
+);
+
+ALTER TABLE outbox ADD COLUMN campaign_id INT NOT NULL DEFAULT 0;
+ALTER TABLE outbox ADD INDEX (campaign_id);
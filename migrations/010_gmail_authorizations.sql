@@ -0,0 +1,11 @@
+CREATE TABLE gmail_authorizations (
+    id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    account_id INT NOT NULL,
+    sender VARCHAR(64) NOT NULL DEFAULT '',
+    authorized BIGINT NOT NULL,
+    active TINYINT NOT NULL DEFAULT 0,
+    last_success BIGINT NOT NULL DEFAULT 0,
+    last_error TEXT NOT NULL,
+    last_error_at BIGINT NOT NULL DEFAULT 0,
+    UNIQUE (account_id)
+);
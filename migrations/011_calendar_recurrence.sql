@@ -0,0 +1,2 @@
+ALTER TABLE calendar ADD COLUMN recurrence VARCHAR(256) NOT NULL DEFAULT '';
+ALTER TABLE calendar ADD COLUMN exceptions VARCHAR(1024) NOT NULL DEFAULT '';
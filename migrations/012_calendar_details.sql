@@ -0,0 +1,5 @@
+ALTER TABLE calendar ADD COLUMN location VARCHAR(256) NOT NULL DEFAULT '';
+ALTER TABLE calendar ADD COLUMN map_link VARCHAR(512) NOT NULL DEFAULT '';
+ALTER TABLE calendar ADD COLUMN category VARCHAR(32) NOT NULL DEFAULT '';
+ALTER TABLE calendar ADD COLUMN time_zone VARCHAR(64) NOT NULL DEFAULT '';
+ALTER TABLE calendar ADD COLUMN all_day TINYINT NOT NULL DEFAULT 0;
@@ -0,0 +1,10 @@
+CREATE TABLE calendar_rsvps (
+    id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    event_id INT NOT NULL,
+    user_id INT NOT NULL,
+    date VARCHAR(10) NOT NULL,
+    status VARCHAR(8) NOT NULL,
+    updated BIGINT NOT NULL,
+    UNIQUE (event_id, user_id, date),
+    INDEX (user_id)
+);
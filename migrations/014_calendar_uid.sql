@@ -0,0 +1,2 @@
+ALTER TABLE calendar ADD COLUMN uid VARCHAR(256) NOT NULL DEFAULT '';
+ALTER TABLE calendar ADD INDEX (uid);
@@ -0,0 +1,9 @@
+-- Songs reference their article by id. The foreign key follows an article
+-- when move_row gives it a new id and detaches its songs when it is deleted.
+ALTER TABLE songs ADD COLUMN article_id INT NULL;
+ALTER TABLE songs ADD COLUMN position INT NOT NULL DEFAULT 0;
+UPDATE songs JOIN articles ON songs.article = articles.title SET songs.article_id = articles.id;
+UPDATE songs SET position = id;
+ALTER TABLE songs DROP COLUMN article;
+ALTER TABLE songs ADD FOREIGN KEY (article_id) REFERENCES articles(id)
+    ON UPDATE CASCADE ON DELETE SET NULL;
@@ -0,0 +1,10 @@
+-- Rows created by the background workers are read back by a random key
+-- written with them, so concurrent inserts never pick up each other's id.
+ALTER TABLE outbox ADD COLUMN insert_key VARCHAR(16) NOT NULL DEFAULT '';
+ALTER TABLE outbox ADD INDEX (insert_key);
+ALTER TABLE campaigns ADD COLUMN insert_key VARCHAR(16) NOT NULL DEFAULT '';
+ALTER TABLE campaigns ADD INDEX (insert_key);
+ALTER TABLE campaign_recipients ADD COLUMN insert_key VARCHAR(16) NOT NULL DEFAULT '';
+ALTER TABLE campaign_recipients ADD INDEX (insert_key);
+ALTER TABLE newsletters ADD COLUMN insert_key VARCHAR(16) NOT NULL DEFAULT '';
+ALTER TABLE newsletters ADD INDEX (insert_key);
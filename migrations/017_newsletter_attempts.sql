@@ -0,0 +1 @@
+ALTER TABLE newsletters ADD COLUMN attempts INT NOT NULL DEFAULT 0;
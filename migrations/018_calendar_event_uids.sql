@@ -0,0 +1,3 @@
+-- Every event keeps the UID it was first exported with, so re-imports match
+-- events even after move_row has given them a new id.
+UPDATE calendar SET uid = CONCAT('calendar-event-', id, '@olmmcc.tk') WHERE uid = '';
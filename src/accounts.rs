@@ -0,0 +1,81 @@
+use mysql::*;
+
+pub struct Account {
+    pub id: i32,
+    pub email: String,
+    pub role: String,
+    pub subscription_policy: i32,
+    pub password: String,
+    pub refresh_token: String,
+    pub verified: bool,
+}
+
+impl Account {
+    pub fn is_admin(&self) -> bool {
+        self.role == "admin"
+    }
+}
+
+fn account_matches(account: &Account, key: &str, value: &str) -> bool {
+    match key {
+        "id" => account.id.to_string() == value,
+        "email" => account.email == value,
+        _ => true,
+    }
+}
+
+pub async fn get_all_accounts() -> Vec<Account> {
+    get_all_rows("accounts", false)
+        .await
+        .iter()
+        .map(|row| Account {
+            id: from_value(row[0].clone()),
+            email: from_value(row[1].clone()),
+            role: from_value(row[2].clone()),
+            subscription_policy: from_value(row[3].clone()),
+            password: from_value(row[4].clone()),
+            refresh_token: from_value(row[5].clone()),
+            verified: from_value::<i32>(row[6].clone()) == 1,
+        })
+        .collect()
+}
+
+pub async fn find_account(key: &str, value: &str) -> Option<Account> {
+    get_like("accounts", key, value)
+        .await
+        .iter()
+        .map(|row| Account {
+            id: from_value(row[0].clone()),
+            email: from_value(row[1].clone()),
+            role: from_value(row[2].clone()),
+            subscription_policy: from_value(row[3].clone()),
+            password: from_value(row[4].clone()),
+            refresh_token: from_value(row[5].clone()),
+            verified: from_value::<i32>(row[6].clone()) == 1,
+        })
+        .find(|account| account_matches(account, key, value))
+}
+
+pub async fn is_admin_account(id: &str) -> bool {
+    find_account("id", id)
+        .await
+        .map(|account| account.is_admin())
+        .unwrap_or(false)
+}
+
+pub fn is_audience(audience: &str) -> bool {
+    audience == "all_users" || audience == "reminder_subscribers"
+}
+
+pub async fn get_audience(audience: &str) -> Vec<Account> {
+    let minimum_policy = if audience == "reminder_subscribers" {
+        2
+    } else {
+        1
+    };
+    get_all_accounts()
+        .await
+        .into_iter()
+        .filter(|account| account.verified && account.subscription_policy >= minimum_policy)
+        .collect()
+}
@@ -0,0 +1,225 @@
+use chrono::NaiveDate;
+use mysql::*;
+use serde::Serialize;
+
+use std::cmp::Reverse;
+
+pub const MAX_PAGE_SIZE: usize = 50;
+
+#[derive(Serialize)]
+pub struct Song {
+    id: i32,
+    name: String,
+    link: String,
+    role: String,
+    #[serde(skip)]
+    article_id: Option<i32>,
+    position: i32,
+}
+
+#[derive(Serialize)]
+pub struct Article {
+    pub id: i32,
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    pub expiry: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub songs: Option<Vec<Song>>,
+}
+
+impl Article {
+    pub fn expiry_date(&self) -> NaiveDate {
+        NaiveDate::parse_from_str(&self.expiry, "%Y-%m-%d").unwrap()
+    }
+}
+
+pub struct ArticleFilter<'a> {
+    pub search: Option<&'a str>,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+impl ArticleFilter<'_> {
+    fn matches(&self, article: &Article) -> bool {
+        let expiry = article.expiry_date();
+        self.search
+            .is_none_or(|x| article.title.to_lowercase().contains(&x.to_lowercase()))
+            && self.from.is_none_or(|x| expiry >= x)
+            && self.to.is_none_or(|x| expiry <= x)
+    }
+}
+
+pub async fn get_all_articles() -> Vec<Article> {
+    get_all_rows("articles", true)
+        .await
+        .iter()
+        .map(|row| Article {
+            id: from_value(row[0].clone()),
+            title: from_value(row[1].clone()),
+            text: Some(from_value(row[2].clone())),
+            expiry: from_value::<NaiveDate>(row[3].clone())
+                .format("%Y-%m-%d")
+                .to_string(),
+            songs: None,
+        })
+        .collect()
+}
+
+async fn get_songs_like(key: &str, value: &str) -> Vec<Song> {
+    get_like("songs", key, value)
+        .await
+        .into_iter()
+        .map(|x| Song {
+            id: from_value(x[0].clone()),
+            name: from_value(x[1].clone()),
+            link: from_value(x[2].clone()),
+            role: from_value(x[3].clone()),
+            article_id: from_value(x[4].clone()),
+            position: from_value(x[5].clone()),
+        })
+        .collect()
+}
+
+async fn get_song(id: &str) -> Option<Song> {
+    get_songs_like("id", id)
+        .await
+        .into_iter()
+        .find(|song| song.id.to_string() == id)
+}
+
+async fn get_songs_for(article_id: i32) -> Vec<Song> {
+    let mut songs: Vec<Song> = get_songs_like("article_id", &article_id.to_string())
+        .await
+        .into_iter()
+        .filter(|song| song.article_id == Some(article_id))
+        .collect();
+    songs.sort_by_key(|song| (song.position, song.id));
+    songs
+}
+
+pub async fn get_article_songs(article: &Article) -> Vec<Song> {
+    get_songs_for(article.id).await
+}
+
+pub async fn attach_song(song_id: &str, article_id: &str) -> Result<(), String> {
+    let article_id = article_id
+        .parse::<i32>()
+        .map_err(|_| "That article does not exist.".to_string())?;
+    if !row_exists("articles", "id", &article_id.to_string()).await {
+        return Err("That article does not exist.".to_string());
+    }
+    let song = get_song(song_id)
+        .await
+        .ok_or_else(|| "That song does not exist.".to_string())?;
+    if song.article_id == Some(article_id) {
+        return Ok(());
+    }
+    let position = get_songs_for(article_id)
+        .await
+        .iter()
+        .map(|song| song.position)
+        .max()
+        .unwrap_or(0)
+        + 1;
+    change_row_where(
+        "songs",
+        "id",
+        song_id,
+        "article_id",
+        &article_id.to_string(),
+    )
+    .await;
+    change_row_where("songs", "id", song_id, "position", &position.to_string()).await;
+    Ok(())
+}
+
+pub async fn detach_song(song_id: &str) -> Result<(), String> {
+    let song = get_song(song_id)
+        .await
+        .ok_or_else(|| "That song does not exist.".to_string())?;
+    if song.article_id.is_none() {
+        return Ok(());
+    }
+    // change_row_where can only write strings, so the song is written again
+    // without an article to leave article_id NULL.
+    delete_row_where("songs", "id", song_id).await;
+    insert_row(
+        "songs",
+        vec!["id", "name", "link", "role"],
+        vec![song_id, &song.name, &song.link, &song.role],
+    )
+    .await
+    .map_err(|e| e.to_string())
+}
+
+pub async fn reorder_songs(article_id: &str, song_ids: &[i32]) -> Result<(), String> {
+    let article_id = article_id
+        .parse::<i32>()
+        .map_err(|_| "That article does not exist.".to_string())?;
+    let mut current: Vec<i32> = get_songs_for(article_id)
+        .await
+        .iter()
+        .map(|song| song.id)
+        .collect();
+    let mut requested = song_ids.to_vec();
+    current.sort();
+    requested.sort();
+    if current != requested {
+        return Err("Please include every song in this article exactly once.".to_string());
+    }
+    for (position, id) in song_ids.iter().enumerate() {
+        change_row_where(
+            "songs",
+            "id",
+            &id.to_string(),
+            "position",
+            &(position + 1).to_string(),
+        )
+        .await;
+    }
+    Ok(())
+}
+
+pub async fn get_article(id: &str) -> Option<Article> {
+    let mut article = get_all_articles()
+        .await
+        .into_iter()
+        .find(|article| article.id.to_string() == id)?;
+    article.songs = Some(get_article_songs(&article).await);
+    Some(article)
+}
+
+pub async fn get_current_article(today: NaiveDate) -> Option<Article> {
+    let mut article = get_all_articles()
+        .await
+        .into_iter()
+        .filter(|article| article.expiry_date() > today)
+        .max_by_key(|article| article.expiry_date())?;
+    article.songs = Some(get_article_songs(&article).await);
+    Some(article)
+}
+
+pub async fn list_articles(
+    filter: &ArticleFilter<'_>,
+    page: usize,
+    per_page: usize,
+) -> (Vec<Article>, usize) {
+    let mut articles: Vec<Article> = get_all_articles()
+        .await
+        .into_iter()
+        .filter(|article| filter.matches(article))
+        .collect();
+    articles.sort_by_key(|article| Reverse(article.expiry_date()));
+    let total = articles.len();
+    let articles = articles
+        .into_iter()
+        .skip(page.saturating_sub(1).saturating_mul(per_page))
+        .take(per_page)
+        .map(|article| Article {
+            text: None,
+            ..article
+        })
+        .collect();
+    (articles, total)
+}
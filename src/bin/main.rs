@@ -1,3 +1,5 @@
+use hyper::header::{CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_TYPE, SET_COOKIE};
+use hyper::server::conn::AddrStream;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Request, Response, Server};
 use hyper::{Method, StatusCode};
@@ -5,30 +7,112 @@ use std::collections::HashMap;
 use std::convert::Infallible;
 use std::net::SocketAddr;
 
-async fn handle_request(request: Request<Body>) -> Result<Response<Body>, hyper::Error> {
+fn header(request: &Request<Body>, name: &str) -> Option<String> {
+    request
+        .headers()
+        .get(name)
+        .and_then(|x| x.to_str().ok())
+        .map(|x| x.to_string())
+}
+
+fn parse_body(body: &str, content_type: &str) -> Option<HashMap<String, String>> {
+    if let Ok(t) = serde_json::from_str(body) {
+        Some(t)
+    } else if content_type.starts_with("application/x-www-form-urlencoded") {
+        Some(
+            form_urlencoded::parse(body.as_bytes())
+                .into_owned()
+                .collect(),
+        )
+    } else {
+        None
+    }
+}
+
+async fn handle_request(
+    request: Request<Body>,
+    remote_addr: SocketAddr,
+) -> Result<Response<Body>, hyper::Error> {
     let mut response = Response::new(Body::empty());
 
     match request.method() {
         &Method::POST => {
-            let url = request.uri().to_string();
+            let url = request.uri().path().to_string();
+            let query = request.uri().query().unwrap_or_default().to_string();
+            let content_type = header(&request, "content-type").unwrap_or_default();
+            let ip = header(&request, "x-real-ip").unwrap_or_else(|| remote_addr.ip().to_string());
+            let user_agent = header(&request, "user-agent").unwrap_or_default();
+            let csrf_token = header(&request, "x-csrf-token");
+            let session_cookie =
+                header(&request, "cookie").and_then(|x| olmmcc::session_from_cookie(&x));
             let request_vector = &hyper::body::to_bytes(request.into_body()).await?.to_vec();
             let request_body = std::str::from_utf8(request_vector).unwrap();
-            if let Ok::<HashMap<&str, String>, _>(string_body_hash) =
-                serde_json::from_str(request_body)
-            {
-                let body_hash = string_body_hash
+            if let Some(mut string_body_hash) = parse_body(request_body, &content_type) {
+                for (k, v) in form_urlencoded::parse(query.as_bytes()).into_owned() {
+                    string_body_hash.entry(k).or_insert(v);
+                }
+                let mut body_hash: HashMap<&str, &str> = string_body_hash
                     .iter()
-                    .map(|(k, v)| (*k, v.as_str()))
+                    .map(|(k, v)| (k.as_str(), v.as_str()))
                     .collect();
-                let response_body = olmmcc::formulate_response(&url, body_hash).await;
-                *response.body_mut() = Body::from(response_body);
+                let mut cookie_session = false;
+                if let Some(session) = &session_cookie {
+                    if !body_hash.contains_key("session") {
+                        body_hash.insert("session", session);
+                        cookie_session = true;
+                    }
+                }
+                let client = olmmcc::Client {
+                    ip,
+                    user_agent,
+                    cookie_session,
+                    csrf_token,
+                };
+                let api_response = olmmcc::formulate_response(&url, body_hash, &client).await;
+                if let Some(cookie) = api_response.set_cookie {
+                    response
+                        .headers_mut()
+                        .insert(SET_COOKIE, cookie.parse().unwrap());
+                }
+                *response.body_mut() = Body::from(api_response.body);
             } else {
                 *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
                 *response.body_mut() = Body::from(
-                    "The OLMMCC api only supports application/x-www-form-urlencoded.".to_string(),
+                    "The OLMMCC api only supports JSON and application/x-www-form-urlencoded bodies."
+                        .to_string(),
                 );
             }
         }
+        &Method::GET => {
+            let query: HashMap<String, String> =
+                form_urlencoded::parse(request.uri().query().unwrap_or_default().as_bytes())
+                    .into_owned()
+                    .collect();
+            let query = query
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.as_str()))
+                .collect();
+            match olmmcc::formulate_file_response(request.uri().path(), query).await {
+                Some(file) => {
+                    let headers = response.headers_mut();
+                    headers.insert(CONTENT_TYPE, file.content_type.parse().unwrap());
+                    headers.insert(CACHE_CONTROL, file.cache_control.parse().unwrap());
+                    if let Some(filename) = file.filename {
+                        headers.insert(
+                            CONTENT_DISPOSITION,
+                            format!("attachment; filename=\"{}\"", filename)
+                                .parse()
+                                .unwrap(),
+                        );
+                    }
+                    *response.body_mut() = Body::from(file.body);
+                }
+                None => {
+                    *response.status_mut() = StatusCode::NOT_FOUND;
+                    *response.body_mut() = Body::from("The requested file could not be found.");
+                }
+            }
+        }
         _ => {
             *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
             *response.body_mut() = Body::from("The OLMMCC api only supports POST.".to_string());
@@ -39,13 +123,40 @@ async fn handle_request(request: Request<Body>) -> Result<Response<Body>, hyper:
 
 #[tokio::main]
 async fn main() {
+    if std::env::args().nth(1).as_deref() == Some("rotate-token-key") {
+        match olmmcc::rotate_token_key().await {
+            Ok(count) => println!("Re-encrypted {} refresh tokens.", count),
+            Err(e) => {
+                eprintln!("Key rotation failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    match olmmcc::encrypt_plaintext_tokens().await {
+        Ok(0) => {}
+        Ok(count) => println!("Encrypted {} plaintext refresh tokens.", count),
+        Err(e) => eprintln!("Plaintext refresh tokens were not encrypted: {}", e),
+    }
+
     let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
 
-    let make_svc =
-        make_service_fn(|_conn| async { Ok::<_, Infallible>(service_fn(handle_request)) });
+    let make_svc = make_service_fn(|conn: &AddrStream| {
+        let remote_addr = conn.remote_addr();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                handle_request(request, remote_addr)
+            }))
+        }
+    });
 
     let server = Server::bind(&addr).serve(make_svc);
 
+    tokio::spawn(olmmcc::run_outbox_worker());
+    tokio::spawn(olmmcc::run_reminder_scheduler());
+    tokio::spawn(olmmcc::run_newsletter_scheduler());
+
     if let Err(e) = server.await {
         eprintln!("Server error: {}", e);
     }
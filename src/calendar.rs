@@ -0,0 +1,230 @@
+use chrono::{
+    DateTime, Datelike, Duration, FixedOffset, Local, NaiveDate, NaiveDateTime, NaiveTime, Offset,
+    TimeZone,
+};
+use chrono_tz::Tz;
+use mysql::*;
+use serde::Serialize;
+
+use crate::config::*;
+use crate::recurrence::*;
+
+pub const CATEGORIES: &[&str] = &["rehearsal", "concert", "social"];
+
+pub const MAX_RANGE_DAYS: i64 = 366;
+pub const MAX_UPCOMING_EVENTS: usize = 50;
+
+#[derive(Clone, Serialize)]
+pub struct CalendarEvent {
+    pub id: i64,
+    pub title: String,
+    pub date: String,
+    pub start_time: String,
+    pub end_time: String,
+    pub notes: String,
+    pub recurrence: String,
+    pub exceptions: Vec<String>,
+    pub location: String,
+    pub map_link: String,
+    pub category: String,
+    pub time_zone: String,
+    pub all_day: bool,
+    #[serde(skip)]
+    pub uid: String,
+    pub starts_at: Option<String>,
+    pub ends_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rsvp: Option<String>,
+}
+
+pub fn localize(date_time: NaiveDateTime, time_zone: &str) -> DateTime<FixedOffset> {
+    let localized = match time_zone.parse::<Tz>() {
+        Ok(tz) => tz
+            .from_local_datetime(&date_time)
+            .earliest()
+            .map(|x| x.with_timezone(&x.offset().fix())),
+        Err(_) => Local
+            .from_local_datetime(&date_time)
+            .earliest()
+            .map(|x| x.with_timezone(&x.offset().fix())),
+    };
+    localized.unwrap_or_else(|| DateTime::from_utc(date_time, FixedOffset::east(0)))
+}
+
+impl CalendarEvent {
+    pub fn naive_date(&self) -> NaiveDate {
+        NaiveDate::parse_from_str(&self.date, "%Y-%m-%d").unwrap()
+    }
+
+    pub fn start(&self) -> Option<NaiveTime> {
+        if self.all_day {
+            None
+        } else {
+            parse_time(&self.start_time)
+        }
+    }
+
+    pub fn end(&self) -> Option<NaiveTime> {
+        match (self.start(), parse_time(&self.end_time)) {
+            (Some(start), Some(end)) if end > start => Some(end),
+            _ => None,
+        }
+    }
+
+    pub fn start_timestamp(&self) -> i64 {
+        let time = self.start().unwrap_or_else(|| NaiveTime::from_hms(0, 0, 0));
+        localize(self.naive_date().and_time(time), &self.time_zone).timestamp()
+    }
+
+    fn set_date(&mut self, date: String) {
+        self.date = date;
+        let date = self.naive_date();
+        self.starts_at = self
+            .start()
+            .map(|x| localize(date.and_time(x), &self.time_zone).to_rfc3339());
+        self.ends_at = self
+            .end()
+            .map(|x| localize(date.and_time(x), &self.time_zone).to_rfc3339());
+    }
+
+    pub fn occurrences(&self, from: NaiveDate, to: NaiveDate) -> Vec<CalendarEvent> {
+        let start = self.naive_date();
+        let dates = match Recurrence::parse(&self.recurrence) {
+            Ok(recurrence) if !self.recurrence.is_empty() => recurrence.dates(start, from, to),
+            _ if start >= from && start <= to => vec![start],
+            _ => Vec::new(),
+        };
+        dates
+            .into_iter()
+            .map(|date| date.format("%Y-%m-%d").to_string())
+            .filter(|date| !self.exceptions.contains(date))
+            .map(|date| {
+                let mut event = self.clone();
+                event.set_date(date);
+                event
+            })
+            .collect()
+    }
+}
+
+pub fn new_event_uid() -> String {
+    format!("{}@olmmcc.tk", crate::generate_verification_code())
+}
+
+pub fn parse_time(time: &str) -> Option<NaiveTime> {
+    const FORMATS: &[&str] = &["%H:%M", "%H:%M:%S", "%I:%M %p", "%I:%M%p", "%I %p", "%I%p"];
+    let time = time.trim().to_uppercase();
+    FORMATS
+        .iter()
+        .filter_map(|format| NaiveTime::parse_from_str(&time, format).ok())
+        .next()
+}
+
+pub fn check_calendar_value(column: &str, value: &str) -> Option<String> {
+    if value.is_empty() {
+        return None;
+    }
+    match column {
+        "recurrence" => Recurrence::parse(value).err(),
+        "start_time" | "end_time" if parse_time(value).is_none() => {
+            Some(format!("{} is not a valid time.", value))
+        }
+        "category" if !CATEGORIES.contains(&value) => Some(format!(
+            "The category must be one of {}.",
+            CATEGORIES.join(", ")
+        )),
+        "time_zone" if value.parse::<Tz>().is_err() => {
+            Some(format!("{} is not a known time zone.", value))
+        }
+        "all_day" if value != "0" && value != "1" => {
+            Some("All day must be either 0 or 1.".to_string())
+        }
+        _ => None,
+    }
+}
+
+pub fn month_range(year_month: &str) -> Option<(NaiveDate, NaiveDate)> {
+    let first = NaiveDate::parse_from_str(&format!("{}-01", year_month), "%Y-%m-%d").ok()?;
+    let next = if first.month() == 12 {
+        NaiveDate::from_ymd(first.year() + 1, 1, 1)
+    } else {
+        NaiveDate::from_ymd(first.year(), first.month() + 1, 1)
+    };
+    Some((first, next.pred()))
+}
+
+pub async fn get_all_events() -> Vec<CalendarEvent> {
+    get_all_rows("calendar", true)
+        .await
+        .iter()
+        .map(|x| CalendarEvent {
+            id: from_value(x[0].clone()),
+            title: from_value(x[1].clone()),
+            date: from_value::<NaiveDate>(x[2].clone())
+                .format("%Y-%m-%d")
+                .to_string(),
+            start_time: from_value(x[3].clone()),
+            end_time: from_value(x[4].clone()),
+            notes: from_value(x[6].clone()),
+            recurrence: from_value(x[7].clone()),
+            exceptions: from_value::<String>(x[8].clone())
+                .split(',')
+                .map(|x| x.trim().to_string())
+                .filter(|x| !x.is_empty())
+                .collect(),
+            location: from_value(x[9].clone()),
+            map_link: from_value(x[10].clone()),
+            category: from_value(x[11].clone()),
+            time_zone: Some(from_value::<String>(x[12].clone()))
+                .filter(|x| !x.is_empty())
+                .or_else(calendar_time_zone)
+                .unwrap_or_default(),
+            all_day: from_value::<i32>(x[13].clone()) == 1,
+            uid: from_value(x[14].clone()),
+            starts_at: None,
+            ends_at: None,
+            rsvp: None,
+        })
+        .map(|mut event| {
+            event.set_date(event.date.clone());
+            event
+        })
+        .collect()
+}
+
+pub async fn get_event(id: &str) -> Option<CalendarEvent> {
+    get_all_events()
+        .await
+        .into_iter()
+        .find(|event| event.id.to_string() == id)
+}
+
+pub fn in_category(event: &CalendarEvent, category: Option<&str>) -> bool {
+    category.is_none_or(|x| x.is_empty() || event.category == x)
+}
+
+pub async fn get_events_between(
+    from: NaiveDate,
+    to: NaiveDate,
+    category: Option<&str>,
+) -> Vec<CalendarEvent> {
+    let mut events: Vec<CalendarEvent> = get_all_events()
+        .await
+        .iter()
+        .filter(|event| in_category(event, category))
+        .flat_map(|event| event.occurrences(from, to))
+        .collect();
+    events.sort_by_key(|event| event.start_timestamp());
+    events
+}
+
+pub async fn get_upcoming_events(
+    from: NaiveDate,
+    limit: usize,
+    category: Option<&str>,
+) -> Vec<CalendarEvent> {
+    let mut events =
+        get_events_between(from, from + Duration::days(MAX_RANGE_DAYS), category).await;
+    events.truncate(limit);
+    events
+}
@@ -0,0 +1,569 @@
+use chrono::{Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use mysql::*;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use crate::calendar::*;
+use crate::config::*;
+use crate::ical::*;
+use crate::recurrence::*;
+
+const COLUMNS: &[&str] = &[
+    "title",
+    "date",
+    "start_time",
+    "end_time",
+    "notes",
+    "recurrence",
+    "exceptions",
+    "location",
+    "map_link",
+    "category",
+    "time_zone",
+    "all_day",
+];
+
+struct ImportedEvent {
+    uid: String,
+    values: Vec<String>,
+    replaces: Option<(String, String)>,
+}
+
+#[derive(Serialize)]
+pub struct ImportPreview {
+    additions: Vec<Value>,
+    changes: Vec<Value>,
+    missing: Vec<Value>,
+    delete_missing: bool,
+    errors: Vec<String>,
+}
+
+struct Plan {
+    additions: Vec<ImportedEvent>,
+    changes: Vec<(CalendarEvent, Vec<(usize, String)>)>,
+    missing: Vec<CalendarEvent>,
+    errors: Vec<String>,
+}
+
+fn default_time_zone() -> String {
+    calendar_time_zone().unwrap_or_default()
+}
+
+fn to_local(date_time: NaiveDateTime) -> NaiveDateTime {
+    match default_time_zone().parse::<Tz>() {
+        Ok(tz) => Utc
+            .from_utc_datetime(&date_time)
+            .with_timezone(&tz)
+            .naive_local(),
+        Err(_) => Utc
+            .from_utc_datetime(&date_time)
+            .with_timezone(&Local)
+            .naive_local(),
+    }
+}
+
+enum When {
+    Date(NaiveDate),
+    DateTime(NaiveDateTime, String),
+}
+
+fn parse_when(property: &Property) -> Option<When> {
+    let value = property.value.trim();
+    if property.param("VALUE") == Some("DATE") || value.len() == 8 {
+        return parse_date(value).map(When::Date);
+    }
+    let date_time =
+        NaiveDateTime::parse_from_str(value.trim_end_matches('Z'), "%Y%m%dT%H%M%S").ok()?;
+    if value.ends_with('Z') {
+        return Some(When::DateTime(to_local(date_time), String::new()));
+    }
+    let time_zone = property
+        .param("TZID")
+        .filter(|x| x.parse::<Tz>().is_ok())
+        .unwrap_or_default();
+    Some(When::DateTime(date_time, time_zone.to_string()))
+}
+
+fn convert_event(properties: &[Property]) -> Result<ImportedEvent, String> {
+    let find = |name: &str| properties.iter().find(|property| property.name == name);
+    let text = |name: &str| {
+        find(name)
+            .map(|property| unescape_text(&property.value))
+            .unwrap_or_default()
+    };
+    let mut uid = text("UID");
+    let title = text("SUMMARY");
+    let start = find("DTSTART")
+        .and_then(parse_when)
+        .ok_or_else(|| format!("\"{}\" has no valid start date.", title))?;
+    let (date, start_time, time_zone, all_day) = match &start {
+        When::Date(date) => (*date, String::new(), String::new(), true),
+        When::DateTime(date_time, time_zone) => (
+            date_time.date(),
+            date_time.format("%H:%M").to_string(),
+            time_zone.clone(),
+            false,
+        ),
+    };
+    let end_time = match find("DTEND").and_then(parse_when) {
+        Some(When::DateTime(end, _)) if !all_day && end.date() == date => {
+            end.format("%H:%M").to_string()
+        }
+        _ => String::new(),
+    };
+    let replaces = find("RECURRENCE-ID")
+        .and_then(parse_when)
+        .map(|when| match when {
+            When::Date(date) => date,
+            When::DateTime(date_time, _) => date_time.date(),
+        })
+        .map(|date| (uid.clone(), date.format("%Y-%m-%d").to_string()));
+    if let Some((master, date)) = &replaces {
+        uid = format!("{}#{}", master, date);
+    }
+    let recurrence = match replaces {
+        Some(_) => String::new(),
+        None => find("RRULE")
+            .map(|property| property.value.trim().to_string())
+            .unwrap_or_default(),
+    };
+    let exceptions: Vec<String> = properties
+        .iter()
+        .filter(|property| property.name == "EXDATE" && replaces.is_none())
+        .flat_map(|property| property.value.split(','))
+        .filter_map(|x| parse_date(x.trim()))
+        .map(|x| x.format("%Y-%m-%d").to_string())
+        .collect();
+    let location = find("LOCATION");
+    let category = text("CATEGORIES")
+        .split(',')
+        .map(|x| x.trim().to_lowercase())
+        .find(|x| CATEGORIES.contains(&x.as_str()))
+        .unwrap_or_default();
+    let values = vec![
+        title.clone(),
+        date.format("%Y-%m-%d").to_string(),
+        start_time,
+        end_time,
+        text("DESCRIPTION"),
+        recurrence,
+        exceptions.join(","),
+        location
+            .map(|property| unescape_text(&property.value))
+            .unwrap_or_default(),
+        location
+            .and_then(|property| property.param("ALTREP"))
+            .unwrap_or_default()
+            .to_string(),
+        category,
+        time_zone,
+        if all_day { "1" } else { "0" }.to_string(),
+    ];
+    for (column, value) in COLUMNS.iter().zip(values.iter()) {
+        if let Some(e) = check_calendar_value(column, value) {
+            return Err(format!("\"{}\": {}", title, e));
+        }
+    }
+    Ok(ImportedEvent {
+        uid,
+        values,
+        replaces,
+    })
+}
+
+fn add_exception(values: &mut [String], date: &str) {
+    let mut exceptions: Vec<String> = values[6]
+        .split(',')
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_string())
+        .collect();
+    if !exceptions.iter().any(|x| x == date) {
+        exceptions.push(date.to_string());
+    }
+    values[6] = exceptions.join(",");
+}
+
+fn apply_overrides(imported: &mut Vec<ImportedEvent>, existing: &[CalendarEvent]) {
+    let overrides: Vec<(String, String)> =
+        imported.iter().filter_map(|x| x.replaces.clone()).collect();
+    for (uid, date) in overrides {
+        match imported
+            .iter_mut()
+            .find(|event| event.replaces.is_none() && event.uid == uid)
+        {
+            Some(master) => add_exception(&mut master.values, &date),
+            None => {
+                if let Some(event) = find_match(&uid, existing) {
+                    let mut values = event_values(&event);
+                    add_exception(&mut values, &date);
+                    imported.push(ImportedEvent {
+                        uid,
+                        values,
+                        replaces: None,
+                    });
+                }
+            }
+        }
+    }
+}
+
+fn event_values(event: &CalendarEvent) -> Vec<String> {
+    vec![
+        event.title.clone(),
+        event.date.clone(),
+        event.start_time.clone(),
+        event.end_time.clone(),
+        event.notes.clone(),
+        event.recurrence.clone(),
+        event.exceptions.join(","),
+        event.location.clone(),
+        event.map_link.clone(),
+        event.category.clone(),
+        event.time_zone.clone(),
+        if event.all_day { "1" } else { "0" }.to_string(),
+    ]
+}
+
+fn find_match(uid: &str, existing: &[CalendarEvent]) -> Option<CalendarEvent> {
+    existing
+        .iter()
+        .find(|event| !uid.is_empty() && event.uid == uid)
+        .cloned()
+}
+
+async fn plan_import(text: &str) -> Result<Plan, String> {
+    let mut events = Vec::new();
+    let mut errors = Vec::new();
+    for properties in parse_ical_events(text)? {
+        match convert_event(&properties) {
+            Ok(t) => events.push(t),
+            Err(e) => errors.push(e),
+        }
+    }
+    Ok(plan_events(events, get_all_events().await, errors))
+}
+
+fn plan_events(
+    mut events: Vec<ImportedEvent>,
+    existing: Vec<CalendarEvent>,
+    errors: Vec<String>,
+) -> Plan {
+    let mut plan = Plan {
+        additions: Vec::new(),
+        changes: Vec::new(),
+        missing: Vec::new(),
+        errors,
+    };
+    apply_overrides(&mut events, &existing);
+    let mut uids: Vec<String> = Vec::new();
+    let mut seen = Vec::new();
+    for mut imported in events {
+        if imported.uid.is_empty() {
+            imported.uid = new_event_uid();
+        } else if uids.contains(&imported.uid) {
+            plan.errors.push(format!(
+                "\"{}\" appears more than once.",
+                imported.values[0]
+            ));
+            continue;
+        }
+        uids.push(imported.uid.clone());
+        match find_match(&imported.uid, &existing) {
+            Some(event) => {
+                seen.push(event.id);
+                let current = event_values(&event);
+                let changed: Vec<(usize, String)> = imported
+                    .values
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, value)| {
+                        let value = if COLUMNS[*i] == "time_zone" && value.is_empty() {
+                            default_time_zone()
+                        } else {
+                            value.to_string()
+                        };
+                        current[*i] != value
+                    })
+                    .map(|(i, value)| (i, value.clone()))
+                    .collect();
+                if !changed.is_empty() {
+                    plan.changes.push((event, changed));
+                }
+            }
+            None => plan.additions.push(imported),
+        }
+    }
+    plan.missing = existing
+        .into_iter()
+        .filter(|event| !seen.contains(&event.id))
+        .collect();
+    plan
+}
+
+fn describe(plan: &Plan, delete_missing: bool) -> ImportPreview {
+    ImportPreview {
+        additions: plan
+            .additions
+            .iter()
+            .map(|event| {
+                let mut fields: Map<String, Value> = COLUMNS
+                    .iter()
+                    .zip(event.values.iter())
+                    .map(|(column, value)| (column.to_string(), json!(value)))
+                    .collect();
+                fields.insert("uid".to_string(), json!(event.uid));
+                Value::Object(fields)
+            })
+            .collect(),
+        changes: plan
+            .changes
+            .iter()
+            .map(|(event, changed)| {
+                let current = event_values(event);
+                let fields: Map<String, Value> = changed
+                    .iter()
+                    .map(|(i, value)| {
+                        (
+                            COLUMNS[*i].to_string(),
+                            json!({"from": current[*i], "to": value}),
+                        )
+                    })
+                    .collect();
+                json!({"id": event.id, "title": event.title, "changes": fields})
+            })
+            .collect(),
+        missing: plan
+            .missing
+            .iter()
+            .map(|event| json!({"id": event.id, "title": event.title, "date": event.date}))
+            .collect(),
+        delete_missing,
+        errors: plan.errors.clone(),
+    }
+}
+
+pub async fn import_calendar(
+    text: &str,
+    apply: bool,
+    delete_missing: bool,
+) -> Result<ImportPreview, String> {
+    let plan = plan_import(text).await?;
+    let preview = describe(&plan, delete_missing);
+    if !apply {
+        return Ok(preview);
+    }
+    if !plan.errors.is_empty() {
+        return Err(format!("Nothing was imported. {}", plan.errors.join(" ")));
+    }
+    // The database helpers have no transactions, so rows added before a
+    // failed insert are removed again before any event is changed.
+    let mut added: Vec<&str> = Vec::new();
+    for event in &plan.additions {
+        let mut names = COLUMNS.to_vec();
+        names.push("uid");
+        let mut values: Vec<&str> = event.values.iter().map(|x| x.as_str()).collect();
+        values.push(&event.uid);
+        if let Err(e) = insert_row("calendar", names, values).await {
+            for uid in added {
+                delete_row_where("calendar", "uid", uid).await;
+            }
+            return Err(e.to_string());
+        }
+        added.push(&event.uid);
+    }
+    for (event, changed) in &plan.changes {
+        for (i, value) in changed {
+            change_row_where("calendar", "id", &event.id.to_string(), COLUMNS[*i], value).await;
+        }
+    }
+    if delete_missing {
+        for event in &plan.missing {
+            delete_row_where("calendar", "id", &event.id.to_string()).await;
+        }
+    }
+    Ok(preview)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(text: &str) -> Vec<ImportedEvent> {
+        parse_ical_events(text)
+            .unwrap()
+            .iter()
+            .map(|properties| convert_event(properties).unwrap())
+            .collect()
+    }
+
+    fn vevent(lines: &[&str]) -> String {
+        format!(
+            "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\n{}\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n",
+            lines.join("\r\n")
+        )
+    }
+
+    fn existing(id: i64, uid: &str, title: &str) -> CalendarEvent {
+        CalendarEvent {
+            id,
+            title: title.to_string(),
+            date: "2024-03-05".to_string(),
+            start_time: "19:00".to_string(),
+            end_time: String::new(),
+            notes: String::new(),
+            recurrence: String::new(),
+            exceptions: Vec::new(),
+            location: String::new(),
+            map_link: String::new(),
+            category: String::new(),
+            time_zone: String::new(),
+            all_day: false,
+            uid: uid.to_string(),
+            starts_at: None,
+            ends_at: None,
+            rsvp: None,
+        }
+    }
+
+    fn imported(uid: &str, title: &str) -> ImportedEvent {
+        ImportedEvent {
+            uid: uid.to_string(),
+            values: event_values(&existing(0, uid, title)),
+            replaces: None,
+        }
+    }
+
+    fn imported_override(master: &str, date: &str) -> ImportedEvent {
+        let mut event = imported(&format!("{}#{}", master, date), "Override");
+        event.replaces = Some((master.to_string(), date.to_string()));
+        event
+    }
+
+    #[test]
+    fn convert_event_reads_timed_events() {
+        let event = &events(&vevent(&[
+            "UID:abc@example.com",
+            "SUMMARY:Rehearsal\\, hall",
+            "DTSTART;TZID=Europe/London:20240305T190000",
+            "DTEND;TZID=Europe/London:20240305T210000",
+            "RRULE:FREQ=WEEKLY",
+            "EXDATE;TZID=Europe/London:20240312T190000",
+            "CATEGORIES:Rehearsal",
+            "LOCATION;ALTREP=\"https://maps.example.com\":Hall",
+        ]))[0];
+        assert_eq!(event.uid, "abc@example.com");
+        assert_eq!(
+            event.values,
+            vec![
+                "Rehearsal, hall",
+                "2024-03-05",
+                "19:00",
+                "21:00",
+                "",
+                "FREQ=WEEKLY",
+                "2024-03-12",
+                "Hall",
+                "https://maps.example.com",
+                "rehearsal",
+                "Europe/London",
+                "0",
+            ]
+        );
+        assert!(event.replaces.is_none());
+    }
+
+    #[test]
+    fn convert_event_reads_all_day_events_and_overrides() {
+        let event = &events(&vevent(&[
+            "UID:abc@example.com",
+            "SUMMARY:Concert",
+            "DTSTART;VALUE=DATE:20240305",
+            "RECURRENCE-ID;VALUE=DATE:20240305",
+            "RRULE:FREQ=DAILY",
+        ]))[0];
+        assert_eq!(event.uid, "abc@example.com#2024-03-05");
+        assert_eq!(event.values[2], "");
+        assert_eq!(event.values[5], "");
+        assert_eq!(event.values[11], "1");
+        assert_eq!(
+            event.replaces,
+            Some(("abc@example.com".to_string(), "2024-03-05".to_string()))
+        );
+    }
+
+    #[test]
+    fn convert_event_rejects_invalid_events() {
+        let text = vevent(&[
+            "UID:a",
+            "SUMMARY:Broken",
+            "DTSTART:20240305T190000",
+            "RRULE:FREQ=HOURLY",
+        ]);
+        let properties = &parse_ical_events(&text).unwrap()[0];
+        assert!(convert_event(properties).is_err());
+        let text = vevent(&["UID:a", "SUMMARY:Undated"]);
+        let properties = &parse_ical_events(&text).unwrap()[0];
+        assert!(convert_event(properties).is_err());
+    }
+
+    #[test]
+    fn apply_overrides_adds_exceptions_to_masters() {
+        let mut stored = existing(7, "c", "Stored");
+        stored.exceptions = vec!["2024-03-01".to_string()];
+        let mut events = vec![
+            imported("a", "Master"),
+            imported_override("a", "2024-03-12"),
+            imported_override("c", "2024-03-19"),
+        ];
+        apply_overrides(&mut events, &[stored]);
+        assert_eq!(events[0].values[6], "2024-03-12");
+        let master = events
+            .iter()
+            .find(|x| x.uid == "c" && x.replaces.is_none())
+            .unwrap();
+        assert_eq!(master.values[0], "Stored");
+        assert_eq!(master.values[6], "2024-03-01,2024-03-19");
+    }
+
+    #[test]
+    fn plan_matches_events_by_uid_only() {
+        let stored = vec![
+            existing(1, "calendar-event-2@olmmcc.tk", "Moved"),
+            existing(2, "other", "Kept"),
+            existing(3, "gone", "Gone"),
+        ];
+        let mut changed = imported("calendar-event-2@olmmcc.tk", "Renamed");
+        changed.values[2] = "20:00".to_string();
+        let plan = plan_events(
+            vec![changed, imported("other", "Kept"), imported("", "New")],
+            stored,
+            Vec::new(),
+        );
+        assert_eq!(plan.changes.len(), 1);
+        let (event, fields) = &plan.changes[0];
+        assert_eq!(event.id, 1);
+        assert_eq!(
+            fields,
+            &vec![(0, "Renamed".to_string()), (2, "20:00".to_string())]
+        );
+        assert_eq!(plan.additions.len(), 1);
+        assert!(!plan.additions[0].uid.is_empty());
+        assert_eq!(
+            plan.missing.iter().map(|x| x.id).collect::<Vec<_>>(),
+            vec![3]
+        );
+        assert!(plan.errors.is_empty());
+    }
+
+    #[test]
+    fn plan_reports_duplicate_uids() {
+        let plan = plan_events(
+            vec![imported("a", "First"), imported("a", "Second")],
+            Vec::new(),
+            Vec::new(),
+        );
+        assert_eq!(plan.additions.len(), 1);
+        assert_eq!(plan.errors.len(), 1);
+    }
+}
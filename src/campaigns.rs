@@ -0,0 +1,268 @@
+use mysql::*;
+use serde::Serialize;
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use crate::config::*;
+use crate::mailer::Email;
+use crate::outbox::*;
+use crate::unsubscribe::*;
+use crate::{insert_row_with_id, unix_time};
+
+pub const TRACKING_PIXEL: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x01, 0x44, 0x00, 0x3b,
+];
+
+#[derive(Serialize)]
+pub struct Campaign {
+    id: i32,
+    subject: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    html: Option<String>,
+    sender: String,
+    audience: String,
+    delivery: String,
+    track_opens: bool,
+    created: i64,
+    count: i32,
+    stats: CampaignStats,
+}
+
+#[derive(Default, Serialize)]
+pub struct CampaignStats {
+    pending: i32,
+    sent: i32,
+    failed: i32,
+    opened: i32,
+}
+
+#[derive(Serialize)]
+pub struct CampaignRecipient {
+    id: i32,
+    account_id: i32,
+    email: String,
+    status: String,
+    last_error: String,
+    sent: i64,
+    opened: i64,
+}
+
+pub async fn create_campaign(
+    email: &Email,
+    sender: &str,
+    audience: &str,
+    delivery: &str,
+    track_opens: bool,
+) -> i32 {
+    insert_row_with_id(
+        "campaigns",
+        vec![
+            "subject",
+            "body",
+            "html",
+            "sender",
+            "audience",
+            "delivery",
+            "track_opens",
+            "created",
+            "count",
+        ],
+        vec![
+            &email.subject,
+            &email.body,
+            email.html.as_deref().unwrap_or_default(),
+            sender,
+            audience,
+            delivery,
+            if track_opens { "1" } else { "0" },
+            &unix_time().to_string(),
+            "0",
+        ],
+    )
+    .await
+    .unwrap()
+}
+
+pub async fn add_campaign_recipient(campaign_id: i32, account_id: i32, email: &str) -> i32 {
+    insert_row_with_id(
+        "campaign_recipients",
+        vec!["campaign_id", "account_id", "email", "outbox_id", "opened"],
+        vec![
+            &campaign_id.to_string(),
+            &account_id.to_string(),
+            email,
+            "0",
+            "0",
+        ],
+    )
+    .await
+    .unwrap()
+}
+
+pub async fn set_recipient_outbox(recipient_ids: &[i32], outbox_id: i32) {
+    for id in recipient_ids {
+        change_row_where(
+            "campaign_recipients",
+            "id",
+            &id.to_string(),
+            "outbox_id",
+            &outbox_id.to_string(),
+        )
+        .await;
+    }
+}
+
+pub async fn finish_campaign(campaign_id: i32, count: usize) {
+    change_row_where(
+        "campaigns",
+        "id",
+        &campaign_id.to_string(),
+        "count",
+        &count.to_string(),
+    )
+    .await;
+}
+
+fn tracking_pixel_url(recipient_id: i32) -> Result<String, String> {
+    let id = recipient_id.to_string();
+    Ok(format!(
+        "{}/open?id={}&token={}",
+        api_url(),
+        id,
+        signed_token("open", &id)?
+    ))
+}
+
+pub fn add_tracking_pixel(email: &mut Email, recipient_id: i32) -> Result<(), String> {
+    if let Some(html) = &mut email.html {
+        let pixel = format!(
+            "<img src=\"{}\" width=\"1\" height=\"1\" alt=\"\" style=\"display:none\">",
+            tracking_pixel_url(recipient_id)?
+        );
+        match html.rfind("</body>") {
+            Some(i) => html.insert_str(i, &pixel),
+            None => html.push_str(&pixel),
+        }
+    }
+    Ok(())
+}
+
+pub async fn record_open(id: &str, token: &str) {
+    if !verify_signed_token("open", id, token) {
+        return;
+    }
+    let first_open = get_like("campaign_recipients", "id", id)
+        .await
+        .iter()
+        .any(|row| {
+            from_value::<i32>(row[0].clone()).to_string() == id
+                && from_value::<i64>(row[5].clone()) == 0
+        });
+    if first_open {
+        change_row_where(
+            "campaign_recipients",
+            "id",
+            id,
+            "opened",
+            &unix_time().to_string(),
+        )
+        .await;
+    }
+}
+
+async fn get_campaign_recipients(campaign_id: &str) -> Vec<(i32, CampaignRecipient)> {
+    let deliveries = get_campaign_deliveries(campaign_id).await;
+    get_like("campaign_recipients", "campaign_id", campaign_id)
+        .await
+        .iter()
+        .filter(|row| {
+            campaign_id == "%" || from_value::<i32>(row[1].clone()).to_string() == campaign_id
+        })
+        .map(|row| {
+            let delivery = deliveries.get(&from_value::<i32>(row[4].clone()));
+            (
+                from_value(row[1].clone()),
+                CampaignRecipient {
+                    id: from_value(row[0].clone()),
+                    account_id: from_value(row[2].clone()),
+                    email: from_value(row[3].clone()),
+                    status: delivery
+                        .map(|x| x.status.clone())
+                        .unwrap_or_else(|| "unknown".to_string()),
+                    last_error: delivery.map(|x| x.last_error.clone()).unwrap_or_default(),
+                    sent: delivery.map(|x| x.sent).unwrap_or_default(),
+                    opened: from_value(row[5].clone()),
+                },
+            )
+        })
+        .collect()
+}
+
+fn campaign_stats(recipients: &[CampaignRecipient]) -> CampaignStats {
+    let mut stats = CampaignStats::default();
+    for recipient in recipients {
+        match recipient.status.as_str() {
+            "sent" => stats.sent += 1,
+            "failed" => stats.failed += 1,
+            _ => stats.pending += 1,
+        }
+        if recipient.opened > 0 {
+            stats.opened += 1;
+        }
+    }
+    stats
+}
+
+async fn get_campaigns_like(key: &str, value: &str) -> Vec<Campaign> {
+    get_like("campaigns", key, value)
+        .await
+        .iter()
+        .map(|row| Campaign {
+            id: from_value(row[0].clone()),
+            subject: from_value(row[1].clone()),
+            body: Some(from_value(row[2].clone())),
+            html: Some(from_value(row[3].clone())),
+            sender: from_value(row[4].clone()),
+            audience: from_value(row[5].clone()),
+            delivery: from_value(row[6].clone()),
+            track_opens: from_value::<i32>(row[7].clone()) == 1,
+            created: from_value(row[8].clone()),
+            count: from_value(row[9].clone()),
+            stats: CampaignStats::default(),
+        })
+        .collect()
+}
+
+pub async fn get_campaigns() -> Vec<Campaign> {
+    let mut recipients: HashMap<i32, Vec<CampaignRecipient>> = HashMap::new();
+    for (campaign_id, recipient) in get_campaign_recipients("%").await {
+        recipients.entry(campaign_id).or_default().push(recipient);
+    }
+    let mut campaigns = get_campaigns_like("id", "%").await;
+    for campaign in &mut campaigns {
+        campaign.body = None;
+        campaign.html = None;
+        campaign.stats = campaign_stats(recipients.get(&campaign.id).map_or(&[], |x| x));
+    }
+    campaigns.sort_by_key(|campaign| Reverse(campaign.created));
+    campaigns
+}
+
+pub async fn get_campaign(id: &str) -> Option<(Campaign, Vec<CampaignRecipient>)> {
+    let mut campaign = get_campaigns_like("id", id)
+        .await
+        .into_iter()
+        .find(|campaign| campaign.id.to_string() == id)?;
+    let recipients: Vec<CampaignRecipient> = get_campaign_recipients(id)
+        .await
+        .into_iter()
+        .map(|(_, recipient)| recipient)
+        .collect();
+    campaign.stats = campaign_stats(&recipients);
+    Some((campaign, recipients))
+}
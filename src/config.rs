@@ -0,0 +1,139 @@
+use std::env;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+fn var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|x| !x.is_empty())
+}
+
+pub fn cookie_sessions() -> bool {
+    var("OLMMCC_COOKIE_SESSIONS").as_deref() == Some("1")
+}
+
+pub fn cookie_domain() -> Option<String> {
+    var("OLMMCC_COOKIE_DOMAIN")
+}
+
+fn seconds(name: &str, default: i64) -> i64 {
+    var(name).and_then(|x| x.parse().ok()).unwrap_or(default)
+}
+
+fn number<T: FromStr>(name: &str, default: T) -> T {
+    var(name).and_then(|x| x.parse().ok()).unwrap_or(default)
+}
+
+pub fn session_idle_lifetime(remember: bool) -> i64 {
+    if remember {
+        seconds("OLMMCC_REMEMBER_IDLE_LIFETIME", 30 * 24 * 60 * 60)
+    } else {
+        seconds("OLMMCC_SESSION_IDLE_LIFETIME", 2 * 60 * 60)
+    }
+}
+
+pub fn session_max_lifetime(remember: bool) -> i64 {
+    if remember {
+        seconds("OLMMCC_REMEMBER_MAX_LIFETIME", 90 * 24 * 60 * 60)
+    } else {
+        seconds("OLMMCC_SESSION_MAX_LIFETIME", 24 * 60 * 60)
+    }
+}
+
+pub fn mail_transport() -> String {
+    var("OLMMCC_MAIL_TRANSPORT").unwrap_or_else(|| "gmail".to_string())
+}
+
+pub fn mail_from() -> String {
+    var("OLMMCC_MAIL_FROM").unwrap_or_else(|| "noreply@olmmcc.tk".to_string())
+}
+
+pub fn smtp_host() -> String {
+    var("OLMMCC_SMTP_HOST").unwrap_or_else(|| "localhost".to_string())
+}
+
+pub fn smtp_username() -> String {
+    var("OLMMCC_SMTP_USERNAME").unwrap_or_default()
+}
+
+pub fn smtp_password() -> String {
+    var("OLMMCC_SMTP_PASSWORD").unwrap_or_default()
+}
+
+pub fn mail_directory() -> PathBuf {
+    PathBuf::from(var("OLMMCC_MAIL_DIRECTORY").unwrap_or_else(|| "/tmp/olmmcc-mail".to_string()))
+}
+
+pub fn contact_email() -> String {
+    var("OLMMCC_CONTACT_EMAIL").unwrap_or_else(|| "justus@olmmcc.tk".to_string())
+}
+
+pub fn outbox_poll_interval() -> u64 {
+    seconds("OLMMCC_OUTBOX_POLL_INTERVAL", 5) as u64
+}
+
+pub fn outbox_max_attempts() -> i32 {
+    number("OLMMCC_OUTBOX_MAX_ATTEMPTS", 8)
+}
+
+pub fn mail_batch_size() -> usize {
+    number("OLMMCC_MAIL_BATCH_SIZE", 50).max(1)
+}
+
+pub fn mail_batch_interval() -> i64 {
+    seconds("OLMMCC_MAIL_BATCH_INTERVAL", 60).max(0)
+}
+
+pub fn mail_delivery() -> String {
+    var("OLMMCC_MAIL_DELIVERY").unwrap_or_else(|| "individual".to_string())
+}
+
+pub fn newsletter_poll_interval() -> u64 {
+    seconds("OLMMCC_NEWSLETTER_POLL_INTERVAL", 60) as u64
+}
+
+pub fn newsletter_send_timeout() -> i64 {
+    seconds("OLMMCC_NEWSLETTER_SEND_TIMEOUT", 15 * 60)
+}
+
+pub fn gmail_client_secret() -> String {
+    var("OLMMCC_GMAIL_CLIENT_SECRET")
+        .unwrap_or_else(|| "/home/justus/client_secret.json".to_string())
+}
+
+pub fn gmail_redirect_uri() -> String {
+    var("OLMMCC_GMAIL_REDIRECT_URI").unwrap_or_else(|| format!("{}/admin/email/", site_url()))
+}
+
+pub fn site_url() -> String {
+    var("OLMMCC_SITE_URL").unwrap_or_else(|| "https://www.olmmcc.tk".to_string())
+}
+
+pub fn api_url() -> String {
+    var("OLMMCC_API_URL").unwrap_or_else(|| "https://api.olmmcc.tk".to_string())
+}
+
+pub fn unsubscribe_secret() -> Result<String, String> {
+    var("OLMMCC_UNSUBSCRIBE_SECRET").ok_or_else(|| {
+        "OLMMCC_UNSUBSCRIBE_SECRET must be set to sign unsubscribe links.".to_string()
+    })
+}
+
+pub fn token_key() -> Result<String, String> {
+    var("OLMMCC_TOKEN_KEY")
+        .ok_or_else(|| "OLMMCC_TOKEN_KEY must be set to encrypt stored OAuth tokens.".to_string())
+}
+
+pub fn previous_token_key() -> Option<String> {
+    var("OLMMCC_PREVIOUS_TOKEN_KEY")
+}
+
+pub fn calendar_time_zone() -> Option<String> {
+    var("OLMMCC_CALENDAR_TIME_ZONE")
+}
+
+pub fn reminder_lead_time() -> i64 {
+    seconds("OLMMCC_REMINDER_LEAD_TIME", 24 * 60 * 60)
+}
+
+pub fn reminder_poll_interval() -> u64 {
+    seconds("OLMMCC_REMINDER_POLL_INTERVAL", 5 * 60) as u64
+}
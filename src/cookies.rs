@@ -0,0 +1,102 @@
+use serde_json::Value;
+use session::Session;
+
+use crate::config::*;
+
+pub const SESSION_COOKIE: &str = "olmmcc_session";
+
+const CSRF_EXEMPT_URLS: &[&str] = &[
+    "/get_songs",
+    "/get_articles",
+    "/get_article",
+    "/get_image_list",
+    "/get_calendar_events",
+    "/get_calendar_range",
+    "/get_upcoming_events",
+    "/get_event_attendance",
+    "/signup",
+    "/login",
+    "/admin_login",
+    "/get_account",
+    "/get_active_sessions",
+    "/get_session_expiry",
+    "/get_database",
+    "/get_row_titles",
+    "/is_gmail_working",
+    "/get_gmail_senders",
+    "/unsubscribe",
+    "/count_recipients",
+    "/get_outbox",
+    "/get_campaigns",
+    "/get_campaign",
+    "/get_newsletters",
+    "/get_email_templates",
+];
+
+pub fn session_from_cookie(header: &str) -> Option<String> {
+    if !cookie_sessions() {
+        return None;
+    }
+    header
+        .split(';')
+        .filter_map(|x| {
+            let mut pair = x.trim().splitn(2, '=');
+            match (pair.next(), pair.next()) {
+                (Some(SESSION_COOKIE), Some(value)) if !value.is_empty() => Some(value.to_string()),
+                _ => None,
+            }
+        })
+        .next()
+}
+
+fn session_cookie(value: &str, max_age: Option<i64>) -> String {
+    let mut cookie = format!(
+        "{}={}; Path=/; Secure; HttpOnly; SameSite=Strict",
+        SESSION_COOKIE, value
+    );
+    if let Some(domain) = cookie_domain() {
+        cookie.push_str(&format!("; Domain={}", domain));
+    }
+    if let Some(max_age) = max_age {
+        cookie.push_str(&format!("; Max-Age={}", max_age));
+    }
+    cookie
+}
+
+pub fn requires_csrf(url: &str) -> bool {
+    !CSRF_EXEMPT_URLS.contains(&url)
+}
+
+pub async fn csrf_matches(session_id: &str, csrf_token: Option<&str>) -> bool {
+    if let (Some(mut session), Some(token)) = (Session::from_id(session_id).await, csrf_token) {
+        if let Some(expected) = session.get("csrf_token").await {
+            return !expected.is_empty() && expected == token;
+        }
+    }
+    false
+}
+
+pub fn take_session_cookie(url: &str, body: String) -> (String, Option<String>) {
+    match url {
+        "/signup" | "/login" | "/admin_login" => {
+            if let Ok(Value::Object(mut map)) = serde_json::from_str(&body) {
+                if let Some(Value::String(session)) = map.remove("session") {
+                    let max_age = if map.get("remember") == Some(&Value::Bool(true)) {
+                        Some(session_max_lifetime(true))
+                    } else {
+                        None
+                    };
+                    let cookie = session_cookie(&session, max_age);
+                    return (Value::Object(map).to_string(), Some(cookie));
+                }
+            }
+            (body, None)
+        }
+        "/kill_session" => (body, Some(session_cookie("", Some(0)))),
+        "/delete_account" => match serde_json::from_str::<Value>(&body) {
+            Ok(ref json) if json["success"] == true => (body, Some(session_cookie("", Some(0)))),
+            _ => (body, None),
+        },
+        _ => (body, None),
+    }
+}
@@ -0,0 +1,323 @@
+use hyper::{Body, Client, Request};
+use hyper_tls::HttpsConnector;
+use mysql::*;
+use serde::Serialize;
+use serde_json::Value;
+
+use std::fs;
+use std::sync::Mutex;
+
+use crate::accounts::*;
+use crate::config::*;
+use crate::secrets::*;
+use crate::unix_time;
+
+const GMAIL_SCOPE: &str = "https://mail.google.com/";
+
+#[derive(Clone, Serialize)]
+pub struct GmailAuthorization {
+    account_id: i32,
+    sender: String,
+    authorized: i64,
+    active: bool,
+    last_success: i64,
+    last_error: String,
+    last_error_at: i64,
+}
+
+impl GmailAuthorization {
+    pub fn account_id(&self) -> i32 {
+        self.account_id
+    }
+
+    pub fn sender(&self) -> &str {
+        &self.sender
+    }
+
+    pub fn token_age(&self) -> i64 {
+        unix_time() - self.authorized
+    }
+}
+
+struct CachedToken {
+    account_id: i32,
+    access_token: String,
+    expires: i64,
+}
+
+static ACCESS_TOKEN: Mutex<Option<CachedToken>> = Mutex::new(None);
+
+struct ClientSecret {
+    client_id: String,
+    client_secret: String,
+}
+
+fn client_secret() -> Result<ClientSecret, String> {
+    let contents = fs::read_to_string(gmail_client_secret()).map_err(|e| e.to_string())?;
+    let json: Value = serde_json::from_str(&contents).map_err(|e| e.to_string())?;
+    let json = ["web", "installed"]
+        .iter()
+        .map(|key| &json[key])
+        .find(|x| x.is_object())
+        .unwrap_or(&json);
+    match (json["client_id"].as_str(), json["client_secret"].as_str()) {
+        (Some(id), Some(secret)) => Ok(ClientSecret {
+            client_id: id.to_string(),
+            client_secret: secret.to_string(),
+        }),
+        _ => Err("The Gmail client secret file is missing client_id or client_secret.".to_string()),
+    }
+}
+
+async fn request_json(request: Request<Body>) -> Result<Value, String> {
+    let response = Client::builder()
+        .build::<_, Body>(HttpsConnector::new())
+        .request(request)
+        .await
+        .map_err(|e| e.to_string())?;
+    let status = response.status();
+    let bytes = hyper::body::to_bytes(response.into_body())
+        .await
+        .map_err(|e| e.to_string())?;
+    let json: Value = serde_json::from_slice(&bytes).unwrap_or_default();
+    if status.is_success() {
+        Ok(json)
+    } else {
+        Err(json["error_description"]
+            .as_str()
+            .or_else(|| json["error"]["message"].as_str())
+            .or_else(|| json["error"].as_str())
+            .map(|x| x.to_string())
+            .unwrap_or_else(|| format!("Google responded with {}.", status)))
+    }
+}
+
+async fn request_token(params: &[(&str, &str)]) -> Result<Value, String> {
+    let body = form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(params)
+        .finish();
+    let request = Request::post("https://oauth2.googleapis.com/token")
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(Body::from(body))
+        .map_err(|e| e.to_string())?;
+    request_json(request).await
+}
+
+async fn get_sender_address(access_token: &str) -> Result<String, String> {
+    let request = Request::get("https://gmail.googleapis.com/gmail/v1/users/me/profile")
+        .header("Authorization", format!("Bearer {}", access_token))
+        .body(Body::empty())
+        .map_err(|e| e.to_string())?;
+    request_json(request).await?["emailAddress"]
+        .as_str()
+        .map(|x| x.to_string())
+        .ok_or_else(|| "Gmail did not report the mailbox address.".to_string())
+}
+
+pub fn auth_url(state: &str) -> Result<String, String> {
+    let secret = client_secret()?;
+    let query = form_urlencoded::Serializer::new(String::new())
+        .append_pair("scope", GMAIL_SCOPE)
+        .append_pair("include_granted_scopes", "true")
+        .append_pair("prompt", "consent")
+        .append_pair("redirect_uri", &gmail_redirect_uri())
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &secret.client_id)
+        .append_pair("access_type", "offline")
+        .append_pair("state", state)
+        .finish();
+    Ok(format!(
+        "https://accounts.google.com/o/oauth2/v2/auth?{}",
+        query
+    ))
+}
+
+fn cache_token(account_id: i32, token: &Value) -> Option<String> {
+    let access_token = token["access_token"].as_str()?.to_string();
+    let expires = unix_time() + token["expires_in"].as_i64().unwrap_or(3600);
+    *ACCESS_TOKEN.lock().unwrap() = Some(CachedToken {
+        account_id,
+        access_token: access_token.clone(),
+        expires,
+    });
+    Some(access_token)
+}
+
+fn cached_token(account_id: i32) -> Option<String> {
+    match &*ACCESS_TOKEN.lock().unwrap() {
+        Some(cached) if cached.account_id == account_id && cached.expires > unix_time() + 60 => {
+            Some(cached.access_token.clone())
+        }
+        _ => None,
+    }
+}
+
+async fn get_authorizations_like(key: &str, value: &str) -> Vec<GmailAuthorization> {
+    get_like("gmail_authorizations", key, value)
+        .await
+        .iter()
+        .map(|row| GmailAuthorization {
+            account_id: from_value(row[1].clone()),
+            sender: from_value(row[2].clone()),
+            authorized: from_value(row[3].clone()),
+            active: from_value::<i32>(row[4].clone()) == 1,
+            last_success: from_value(row[5].clone()),
+            last_error: from_value(row[6].clone()),
+            last_error_at: from_value(row[7].clone()),
+        })
+        .collect()
+}
+
+pub async fn get_authorizations() -> Vec<GmailAuthorization> {
+    get_authorizations_like("account_id", "%").await
+}
+
+pub async fn get_sender() -> Option<GmailAuthorization> {
+    get_authorizations_like("active", "1")
+        .await
+        .into_iter()
+        .find(|authorization| authorization.active)
+}
+
+pub async fn set_sender(account_id: &str) -> bool {
+    if !get_authorizations()
+        .await
+        .iter()
+        .any(|authorization| authorization.account_id.to_string() == account_id)
+    {
+        return false;
+    }
+    change_row_where("gmail_authorizations", "active", "1", "active", "0").await;
+    change_row_where(
+        "gmail_authorizations",
+        "account_id",
+        account_id,
+        "active",
+        "1",
+    )
+    .await;
+    *ACCESS_TOKEN.lock().unwrap() = None;
+    true
+}
+
+pub async fn authorize(account_id: &str, code: &str) -> Result<String, String> {
+    token_key()?;
+    let secret = client_secret()?;
+    let redirect_uri = gmail_redirect_uri();
+    let token = request_token(&[
+        ("code", code),
+        ("client_id", secret.client_id.as_str()),
+        ("client_secret", secret.client_secret.as_str()),
+        ("redirect_uri", redirect_uri.as_str()),
+        ("grant_type", "authorization_code"),
+    ])
+    .await?;
+    let refresh_token = token["refresh_token"]
+        .as_str()
+        .ok_or_else(|| "Google did not return a refresh token. Please try again.".to_string())?;
+    let access_token = token["access_token"]
+        .as_str()
+        .ok_or_else(|| "Google did not return an access token. Please try again.".to_string())?;
+    let sender = get_sender_address(access_token).await?;
+    change_row_where(
+        "accounts",
+        "id",
+        account_id,
+        "refresh_token",
+        &encrypt_token(refresh_token)?,
+    )
+    .await;
+    delete_row_where("gmail_authorizations", "account_id", account_id).await;
+    insert_row(
+        "gmail_authorizations",
+        vec![
+            "account_id",
+            "sender",
+            "authorized",
+            "active",
+            "last_success",
+            "last_error",
+            "last_error_at",
+        ],
+        vec![
+            account_id,
+            &sender,
+            &unix_time().to_string(),
+            "0",
+            "0",
+            "",
+            "0",
+        ],
+    )
+    .await
+    .map_err(|e| e.to_string())?;
+    match get_sender().await {
+        Some(active) if active.account_id.to_string() != account_id => {}
+        _ => {
+            set_sender(account_id).await;
+            cache_token(account_id.parse().unwrap(), &token);
+        }
+    }
+    Ok(sender)
+}
+
+pub async fn record_success(account_id: i32) {
+    change_row_where(
+        "gmail_authorizations",
+        "account_id",
+        &account_id.to_string(),
+        "last_success",
+        &unix_time().to_string(),
+    )
+    .await;
+}
+
+pub async fn record_failure(account_id: i32, error: &str) {
+    let account_id = account_id.to_string();
+    change_row_where(
+        "gmail_authorizations",
+        "account_id",
+        &account_id,
+        "last_error",
+        error,
+    )
+    .await;
+    change_row_where(
+        "gmail_authorizations",
+        "account_id",
+        &account_id,
+        "last_error_at",
+        &unix_time().to_string(),
+    )
+    .await;
+}
+
+async fn refresh_access_token(account_id: i32) -> Result<String, String> {
+    let refresh_token = find_account("id", &account_id.to_string())
+        .await
+        .map(|account| account.refresh_token)
+        .filter(|x| !x.is_empty())
+        .ok_or_else(|| "The sending account has no Gmail refresh token.".to_string())?;
+    let refresh_token = decrypt_token(&refresh_token)?;
+    let secret = client_secret()?;
+    let token = request_token(&[
+        ("refresh_token", refresh_token.as_str()),
+        ("client_id", secret.client_id.as_str()),
+        ("client_secret", secret.client_secret.as_str()),
+        ("grant_type", "refresh_token"),
+    ])
+    .await?;
+    cache_token(account_id, &token)
+        .ok_or_else(|| "Google did not return an access token.".to_string())
+}
+
+pub async fn get_access_token(sender: &GmailAuthorization) -> Result<String, String> {
+    if let Some(t) = cached_token(sender.account_id) {
+        return Ok(t);
+    }
+    let result = refresh_access_token(sender.account_id).await;
+    if let Err(e) = &result {
+        record_failure(sender.account_id, e).await;
+    }
+    result
+}
@@ -0,0 +1,429 @@
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Offset, TimeZone, Utc};
+use chrono_tz::{OffsetComponents, OffsetName, Tz};
+
+use std::sync::Mutex;
+
+use crate::calendar::*;
+use crate::recurrence::*;
+
+const PRODUCT_ID: &str = "-//OLMMCC//Calendar//EN";
+const TIME_ZONE_YEARS: i32 = 10;
+
+fn escape_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+fn fold_line(line: &str) -> String {
+    let mut folded = String::new();
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            length = 1;
+        }
+        folded.push(c);
+        length += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+fn format_date(date: NaiveDate) -> String {
+    date.format("%Y%m%d").to_string()
+}
+
+fn format_date_time(date_time: NaiveDateTime) -> String {
+    date_time.format("%Y%m%dT%H%M%S").to_string()
+}
+
+fn format_offset(seconds: i32) -> String {
+    let sign = if seconds < 0 { '-' } else { '+' };
+    let seconds = seconds.abs();
+    format!("{}{:02}{:02}", sign, seconds / 3600, seconds / 60 % 60)
+}
+
+fn observance_lines(tz: Tz, at: NaiveDateTime, from: i32) -> Vec<String> {
+    let offset = tz.offset_from_utc_datetime(&at);
+    let kind = if offset.dst_offset() == Duration::zero() {
+        "STANDARD"
+    } else {
+        "DAYLIGHT"
+    };
+    vec![
+        format!("BEGIN:{}", kind),
+        format!(
+            "DTSTART:{}",
+            format_date_time(at + Duration::seconds(from as i64))
+        ),
+        format!("TZOFFSETFROM:{}", format_offset(from)),
+        format!(
+            "TZOFFSETTO:{}",
+            format_offset(offset.fix().local_minus_utc())
+        ),
+        format!("TZNAME:{}", offset.abbreviation()),
+        format!("END:{}", kind),
+    ]
+}
+
+struct Transitions {
+    tz: Tz,
+    from_year: i32,
+    to_year: i32,
+    instants: Vec<NaiveDateTime>,
+}
+
+static TRANSITIONS: Mutex<Vec<Transitions>> = Mutex::new(Vec::new());
+
+fn utc_offset(tz: Tz, at: NaiveDateTime) -> (i32, Duration) {
+    let offset = tz.offset_from_utc_datetime(&at);
+    (offset.fix().local_minus_utc(), offset.dst_offset())
+}
+
+fn year_start(year: i32) -> NaiveDateTime {
+    NaiveDate::from_ymd(year, 1, 1).and_hms(0, 0, 0)
+}
+
+fn scan_transitions(tz: Tz, from_year: i32, to_year: i32) -> Vec<NaiveDateTime> {
+    let mut instants = Vec::new();
+    let mut at = year_start(from_year);
+    let end = year_start(to_year + 1);
+    while at < end {
+        let next = at + Duration::days(1);
+        if utc_offset(tz, next) != utc_offset(tz, at) {
+            let (mut before, mut after) = (at, next);
+            while after - before > Duration::seconds(1) {
+                let middle = before + Duration::seconds((after - before).num_seconds() / 2);
+                if utc_offset(tz, middle) == utc_offset(tz, before) {
+                    before = middle;
+                } else {
+                    after = middle;
+                }
+            }
+            instants.push(after);
+        }
+        at = next;
+    }
+    instants
+}
+
+fn transitions(tz: Tz, from_year: i32, to_year: i32) -> Vec<NaiveDateTime> {
+    let mut cache = TRANSITIONS.lock().unwrap();
+    let i = match cache.iter().position(|x| x.tz == tz) {
+        Some(i) if cache[i].from_year <= from_year && cache[i].to_year >= to_year => i,
+        Some(i) => {
+            let from_year = from_year.min(cache[i].from_year);
+            let to_year = to_year.max(cache[i].to_year);
+            cache[i] = Transitions {
+                tz,
+                from_year,
+                to_year,
+                instants: scan_transitions(tz, from_year, to_year),
+            };
+            i
+        }
+        None => {
+            cache.push(Transitions {
+                tz,
+                from_year,
+                to_year,
+                instants: scan_transitions(tz, from_year, to_year),
+            });
+            cache.len() - 1
+        }
+    };
+    let (start, end) = (year_start(from_year), year_start(to_year + 1));
+    cache[i]
+        .instants
+        .iter()
+        .filter(|x| **x >= start && **x < end)
+        .copied()
+        .collect()
+}
+
+fn time_zone_lines(name: &str, tz: Tz, from_year: i32, to_year: i32) -> Vec<String> {
+    let start = year_start(from_year);
+    let mut current = utc_offset(tz, start).0;
+    let mut lines = vec!["BEGIN:VTIMEZONE".to_string(), format!("TZID:{}", name)];
+    lines.extend(observance_lines(tz, start, current));
+    for at in transitions(tz, from_year, to_year) {
+        lines.extend(observance_lines(tz, at, current));
+        current = utc_offset(tz, at).0;
+    }
+    lines.push("END:VTIMEZONE".to_string());
+    lines
+}
+
+fn event_lines(event: &CalendarEvent, stamp: &str) -> Vec<String> {
+    let date = event.naive_date();
+    let start = event.start();
+    let tzid = match event.time_zone.parse::<Tz>() {
+        Ok(_) => format!(";TZID={}", event.time_zone),
+        Err(_) => String::new(),
+    };
+    let mut lines = vec![
+        "BEGIN:VEVENT".to_string(),
+        format!("UID:{}", event.uid),
+        format!("DTSTAMP:{}", stamp),
+        format!("SUMMARY:{}", escape_text(&event.title)),
+    ];
+    match start {
+        Some(start) => {
+            let start = date.and_time(start);
+            lines.push(format!("DTSTART{}:{}", tzid, format_date_time(start)));
+            if let Some(end) = event.end() {
+                lines.push(format!(
+                    "DTEND{}:{}",
+                    tzid,
+                    format_date_time(date.and_time(end))
+                ));
+            }
+        }
+        None => {
+            lines.push(format!("DTSTART;VALUE=DATE:{}", format_date(date)));
+            lines.push(format!("DTEND;VALUE=DATE:{}", format_date(date.succ())));
+        }
+    }
+    if !event.notes.is_empty() {
+        lines.push(format!("DESCRIPTION:{}", escape_text(&event.notes)));
+    }
+    if !event.location.is_empty() {
+        lines.push(if event.map_link.is_empty() {
+            format!("LOCATION:{}", escape_text(&event.location))
+        } else {
+            format!(
+                "LOCATION;ALTREP=\"{}\":{}",
+                event.map_link.replace('"', "%22"),
+                escape_text(&event.location)
+            )
+        });
+    }
+    if !event.category.is_empty() {
+        lines.push(format!("CATEGORIES:{}", escape_text(&event.category)));
+    }
+    if let Ok(recurrence) = Recurrence::parse(&event.recurrence) {
+        if !event.recurrence.is_empty() {
+            let rrule = recurrence.to_rrule(|until| match start {
+                None => format_date(until),
+                Some(_) if tzid.is_empty() => format_date_time(until.and_hms(23, 59, 59)),
+                Some(_) => {
+                    let end = localize(until.and_hms(23, 59, 59), &event.time_zone);
+                    format!("{}Z", format_date_time(end.naive_utc()))
+                }
+            });
+            lines.push(format!("RRULE:{}", rrule));
+            for exception in event.exceptions.iter().filter_map(|x| parse_date(x)) {
+                lines.push(match start {
+                    Some(start) => {
+                        format!(
+                            "EXDATE{}:{}",
+                            tzid,
+                            format_date_time(exception.and_time(start))
+                        )
+                    }
+                    None => format!("EXDATE;VALUE=DATE:{}", format_date(exception)),
+                });
+            }
+        }
+    }
+    lines.push("END:VEVENT".to_string());
+    lines
+}
+
+pub fn calendar_to_ical(events: &[CalendarEvent]) -> String {
+    let stamp = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        format!("PRODID:{}", PRODUCT_ID),
+        "CALSCALE:GREGORIAN".to_string(),
+        "METHOD:PUBLISH".to_string(),
+        "X-WR-CALNAME:OLMMCC".to_string(),
+    ];
+    let this_year = Utc::today().year();
+    let mut time_zones: Vec<(&str, Tz, i32, i32)> = Vec::new();
+    for event in events.iter().filter(|event| event.start().is_some()) {
+        if let Ok(tz) = event.time_zone.parse::<Tz>() {
+            let year = event.naive_date().year();
+            match time_zones.iter_mut().find(|x| x.0 == event.time_zone) {
+                Some(x) => {
+                    x.2 = x.2.min(year);
+                    x.3 = x.3.max(year);
+                }
+                None => time_zones.push((&event.time_zone, tz, year, year)),
+            }
+        }
+    }
+    for (name, tz, from_year, to_year) in time_zones {
+        lines.extend(time_zone_lines(
+            name,
+            tz,
+            from_year,
+            to_year.max(this_year) + TIME_ZONE_YEARS,
+        ));
+    }
+    for event in events {
+        lines.extend(event_lines(event, &stamp));
+    }
+    lines.push("END:VCALENDAR".to_string());
+    lines.iter().map(|line| fold_line(line)).collect()
+}
+
+pub struct Property {
+    pub name: String,
+    pub params: Vec<(String, String)>,
+    pub value: String,
+}
+
+impl Property {
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub fn unescape_text(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') | Some('N') => unescaped.push('\n'),
+                Some(t) => unescaped.push(t),
+                None => {}
+            }
+        } else {
+            unescaped.push(c);
+        }
+    }
+    unescaped
+}
+
+fn unfold_lines(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        match (line.chars().next(), lines.last_mut()) {
+            (Some(' '), Some(last)) | (Some('\t'), Some(last)) => last.push_str(&line[1..]),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+fn split_unquoted(text: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut quoted = false;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        if c == '"' {
+            quoted = !quoted;
+        } else if c == separator && !quoted {
+            parts.push(&text[start..i]);
+            start = i + 1;
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+fn parse_property(line: &str) -> Option<Property> {
+    let mut quoted = false;
+    let colon = line.char_indices().find(|(_, c)| {
+        if *c == '"' {
+            quoted = !quoted;
+        }
+        *c == ':' && !quoted
+    })?;
+    let (head, value) = (&line[..colon.0], &line[colon.0 + 1..]);
+    let mut parts = split_unquoted(head, ';').into_iter();
+    let name = parts.next()?.to_uppercase();
+    let params = parts
+        .filter_map(|param| {
+            let mut pair = param.splitn(2, '=');
+            Some((
+                pair.next()?.to_uppercase(),
+                pair.next()?.trim_matches('"').to_string(),
+            ))
+        })
+        .collect();
+    Some(Property {
+        name,
+        params,
+        value: value.to_string(),
+    })
+}
+
+pub fn parse_ical_events(text: &str) -> Result<Vec<Vec<Property>>, String> {
+    let mut events = Vec::new();
+    let mut current: Option<Vec<Property>> = None;
+    let mut nested = 0;
+    for line in unfold_lines(text) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let property = parse_property(&line)
+            .ok_or_else(|| format!("{} is not a valid iCalendar line.", line))?;
+        match (
+            property.name.as_str(),
+            property.value.to_uppercase().as_str(),
+        ) {
+            ("BEGIN", "VEVENT") if current.is_none() => current = Some(Vec::new()),
+            ("END", "VEVENT") if nested == 0 => events.extend(current.take()),
+            ("BEGIN", _) if current.is_some() => nested += 1,
+            ("END", _) if current.is_some() => nested -= 1,
+            _ => {
+                if let Some(event) = &mut current {
+                    if nested == 0 {
+                        event.push(property);
+                    }
+                }
+            }
+        }
+    }
+    if events.is_empty() {
+        Err("The uploaded file does not contain any events.".to_string())
+    } else {
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(year: i32, month: u32, day: u32, hour: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(year, month, day).and_hms(hour, 0, 0)
+    }
+
+    #[test]
+    fn transitions_are_found_and_reused() {
+        let tz: Tz = "Europe/London".parse().unwrap();
+        assert_eq!(
+            transitions(tz, 2024, 2024),
+            vec![at(2024, 3, 31, 1), at(2024, 10, 27, 1)]
+        );
+        assert_eq!(transitions(tz, 2023, 2025).len(), 6);
+        assert_eq!(
+            transitions(tz, 2024, 2024),
+            vec![at(2024, 3, 31, 1), at(2024, 10, 27, 1)]
+        );
+        let cache = TRANSITIONS.lock().unwrap();
+        let cached = cache.iter().find(|x| x.tz == tz).unwrap();
+        assert_eq!((cached.from_year, cached.to_year), (2023, 2025));
+    }
+
+    #[test]
+    fn time_zone_lines_list_each_observance() {
+        let tz: Tz = "America/New_York".parse().unwrap();
+        let lines = time_zone_lines("America/New_York", tz, 2024, 2024);
+        assert_eq!(lines.iter().filter(|x| x.starts_with("BEGIN:")).count(), 4);
+        assert!(lines.contains(&"DTSTART:20240310T020000".to_string()));
+        assert!(lines.contains(&"DTSTART:20241103T020000".to_string()));
+        assert!(lines.contains(&"TZOFFSETTO:-0400".to_string()));
+    }
+}
@@ -1,8 +1,8 @@
-use chrono::NaiveDate;
+use chrono::{Local, NaiveDate, Utc};
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 use scrypt::{scrypt_check, scrypt_simple, ScryptParams};
-use serde::Serialize;
+use serde::Deserialize;
 use serde_json::{json, Map, Value};
 
 use mysql::*;
@@ -10,48 +10,156 @@ use session::Session;
 
 use std::collections::HashMap;
 use std::fs;
-use std::fs::File;
-use std::io::prelude::*;
 use std::iter;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use account_validation::*;
 mod account_validation;
+use accounts::*;
+mod accounts;
+use articles::*;
+mod articles;
+use calendar::*;
+mod calendar;
+use calendar_import::*;
+mod calendar_import;
+use campaigns::*;
+mod campaigns;
+mod config;
+pub use cookies::session_from_cookie;
+use cookies::*;
+mod cookies;
+use gmail_auth::*;
+mod gmail_auth;
+use ical::*;
+mod ical;
+use mailer::*;
+mod mailer;
+use mime::*;
+mod mime;
+pub use newsletters::run_newsletter_scheduler;
+use newsletters::*;
+mod newsletters;
+pub use outbox::run_outbox_worker;
+use outbox::*;
+mod outbox;
+use recurrence::*;
+mod recurrence;
+pub use reminders::run_reminder_scheduler;
+mod reminders;
+use rsvps::*;
+mod rsvps;
+pub use secrets::{encrypt_plaintext_tokens, rotate_token_key};
+mod secrets;
+use sessions::*;
+mod sessions;
+use templates::*;
+mod templates;
+use unsubscribe::*;
+mod unsubscribe;
 
-#[derive(Serialize)]
-struct Song {
+pub struct Client {
+    pub ip: String,
+    pub user_agent: String,
+    pub cookie_session: bool,
+    pub csrf_token: Option<String>,
+}
+
+const IMAGES_DIRECTORY: &str = "/srv/http/images/";
+
+pub struct FileResponse {
+    pub content_type: &'static str,
+    pub cache_control: &'static str,
+    pub filename: Option<String>,
+    pub body: Vec<u8>,
+}
+
+pub struct ApiResponse {
+    pub body: String,
+    pub set_cookie: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct UploadedAttachment {
     name: String,
-    link: String,
-    role: String,
+    content_type: String,
+    data: String,
 }
 
-#[derive(Serialize)]
-struct SongArticle {
-    title: String,
-    text: String,
-    songs: Vec<Song>,
+pub async fn formulate_response(
+    url: &str,
+    body: HashMap<&str, &str>,
+    client: &Client,
+) -> ApiResponse {
+    if client.cookie_session
+        && requires_csrf(url)
+        && !csrf_matches(body["session"], client.csrf_token.as_deref()).await
+    {
+        return ApiResponse {
+            body: message(
+                "Your request could not be verified. Please reload the page and try again.",
+            ),
+            set_cookie: None,
+        };
+    }
+    let response = route(url, body, client).await;
+    if config::cookie_sessions() {
+        let (body, set_cookie) = take_session_cookie(url, response);
+        ApiResponse { body, set_cookie }
+    } else {
+        ApiResponse {
+            body: response,
+            set_cookie: None,
+        }
+    }
 }
 
-#[derive(Serialize)]
-struct CalendarEvent {
-    id: i64,
-    title: String,
-    date: String,
-    start_time: String,
-    end_time: String,
-    notes: String,
+pub async fn formulate_file_response(
+    url: &str,
+    query: HashMap<&str, &str>,
+) -> Option<FileResponse> {
+    match url {
+        "/open" => Some(track_open(query).await),
+        "/calendar.ics" => Some(get_calendar_feed(query).await),
+        _ => match url
+            .strip_prefix("/calendar/")
+            .and_then(|x| x.strip_suffix(".ics"))
+        {
+            Some(id) => get_event_ics(id).await,
+            None => None,
+        },
+    }
 }
 
-pub async fn formulate_response(url: &str, body: HashMap<&str, &str>) -> String {
+async fn route(url: &str, body: HashMap<&str, &str>, client: &Client) -> String {
+    if let Some(session_id) = body.get("session") {
+        if url != "/get_session_expiry" {
+            touch_session(session_id).await;
+        }
+    }
     match url {
         "/get_songs" => get_songs().await,
+        "/get_articles" => get_articles(body).await,
+        "/get_article" => get_article_by_id(body).await,
+        "/attach_song" => attach_article_song(body).await,
+        "/detach_song" => detach_article_song(body).await,
+        "/reorder_songs" => reorder_article_songs(body).await,
         "/hash_password" => hash_password(body).await,
         "/get_image_list" => get_image_list(),
         "/get_calendar_events" => get_calendar_events(body).await,
+        "/get_calendar_range" => get_calendar_range(body).await,
+        "/get_upcoming_events" => get_upcoming_calendar_events(body).await,
+        "/rsvp_event" => rsvp_event(body).await,
+        "/get_event_attendance" => get_event_attendance(body).await,
+        "/import_calendar" => import_calendar_file(body).await,
         "/signup" => signup(body).await,
         "/login" => login(body).await,
-        "/admin_login" => admin_login(body).await,
+        "/admin_login" => admin_login(body, client).await,
         "/kill_session" => kill_session(body).await,
+        "/get_active_sessions" => get_active_sessions(body).await,
+        "/revoke_active_session" => revoke_active_session(body).await,
+        "/revoke_all_sessions" => revoke_all_sessions(body).await,
+        "/get_session_expiry" => session_expiry(body).await,
         "/get_account" => get_account(body).await,
         "/refresh" => refresh(body).await,
         "/change_subscription" => change_subscription(body).await,
@@ -69,8 +177,25 @@ pub async fn formulate_response(url: &str, body: HashMap<&str, &str>) -> String
         "/get_gmail_auth_url" => get_gmail_auth_url(body).await,
         "/is_gmail_working" => is_gmail_working(body).await,
         "/send_gmail_code" => send_gmail_code(body).await,
-        "/verify_account" => verify_account(body).await,
+        "/get_gmail_senders" => get_gmail_senders(body).await,
+        "/set_gmail_sender" => set_gmail_sender(body).await,
+        "/verify_account" => verify_account(body, client).await,
         "/send_email" => send_email(body).await,
+        "/unsubscribe" => unsubscribe(body).await,
+        "/count_recipients" => count_recipients(body).await,
+        "/get_outbox" => get_outbox(body).await,
+        "/resend_email" => resend_email(body).await,
+        "/get_campaigns" => get_email_campaigns(body).await,
+        "/get_campaign" => get_email_campaign(body).await,
+        "/save_newsletter" => save_newsletter_draft(body).await,
+        "/get_newsletters" => list_newsletters(body).await,
+        "/test_newsletter" => test_newsletter(body).await,
+        "/schedule_newsletter" => schedule_newsletter_send(body).await,
+        "/cancel_newsletter" => cancel_newsletter_send(body).await,
+        "/get_email_templates" => get_email_templates(body).await,
+        "/change_email_template" => change_email_template(body).await,
+        "/reset_email_template" => reset_email_template(body).await,
+        "/preview_email_template" => preview_email_template(body).await,
         _ => message(&format!("The provided url {} could not be resolved.", url)),
     }
 }
@@ -84,74 +209,285 @@ fn hash(to_hash: &str) -> String {
 fn hash_match(password: &str, hash: &str) -> bool {
     scrypt_check(password, hash).is_ok()
 }
-
-pub async fn get_songs() -> String {
-    let mut expiry = 0;
-    let current_time = SystemTime::now()
+fn generate_csrf_token() -> String {
+    let mut rng = thread_rng();
+    iter::repeat(())
+        .map(|()| rng.sample(Alphanumeric))
+        .take(32)
+        .collect()
+}
+async fn new_session(body: &HashMap<&str, &str>) -> Session {
+    let remember = body.get("remember") == Some(&"1");
+    // The session store keeps sessions for whole days, so round the absolute
+    // lifetime up rather than letting the store expire them first.
+    let days = (config::session_max_lifetime(remember) + 24 * 60 * 60 - 1) / (24 * 60 * 60);
+    let mut session = Session::new(days, 100).await;
+    session
+        .set("csrf_token", generate_csrf_token())
+        .await
+        .set("remember", if remember { "1" } else { "0" }.to_string())
+        .await;
+    session
+}
+async fn clear_session(session: &mut Session) {
+    const PRESERVED_VARS: &[&str] = &["csrf_token", "remember"];
+    let mut preserved = Vec::new();
+    for var in PRESERVED_VARS {
+        if let Some(t) = session.get(var).await {
+            preserved.push((var, t));
+        }
+    }
+    session.clear().await;
+    for (var, t) in preserved {
+        session.set(var, t).await;
+    }
+}
+fn unix_time() -> i64 {
+    SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
-        .as_secs();
-    let mut article: Vec<SongArticle> = get_all_rows("articles", true)
-        .await
-        .iter()
-        .filter(|row| {
-            let this_expiry = from_value::<NaiveDate>(row[3].clone())
-                .and_hms(0, 0, 0)
-                .timestamp();
-            if this_expiry > expiry && current_time < this_expiry as u64 {
-                expiry = this_expiry;
-                true
-            } else {
-                false
-            }
-        })
-        .map(|x| SongArticle {
-            title: from_value(x[1].clone()),
-            text: from_value(x[2].clone()),
-            songs: Vec::new(),
-        })
+        .as_secs() as i64
+}
+
+async fn insert_row_with_id(
+    table: &str,
+    columns: Vec<&str>,
+    values: Vec<&str>,
+) -> Result<i32, String> {
+    let key = generate_verification_code();
+    let columns = columns
+        .into_iter()
+        .chain(iter::once("insert_key"))
         .collect();
-    match article.pop() {
-        Some(mut t) => {
-            t.songs = get_like("songs", "article", &t.title)
-                .await
-                .into_iter()
-                .map(|x| Song {
-                    name: from_value(x[1].clone()),
-                    link: from_value(x[2].clone()),
-                    role: from_value(x[3].clone()),
-                })
-                .collect();
-            serde_json::to_string(&t).unwrap()
-        }
+    let values = values.into_iter().chain(iter::once(key.as_str())).collect();
+    insert_row(table, columns, values)
+        .await
+        .map_err(|e| e.to_string())?;
+    get_like(table, "insert_key", &key)
+        .await
+        .first()
+        .map(|row| from_value(row[0].clone()))
+        .ok_or_else(|| format!("The new {} row could not be found.", table))
+}
+
+pub async fn get_songs() -> String {
+    match get_current_article(Utc::today().naive_utc()).await {
+        Some(t) => serde_json::to_string(&t).unwrap(),
         None => json!({"title" : ""}).to_string(),
     }
 }
 
+pub async fn get_articles(body: HashMap<&str, &str>) -> String {
+    let filter = ArticleFilter {
+        search: body.get("search").copied().filter(|x| !x.is_empty()),
+        from: body.get("from").and_then(|x| parse_date(x)),
+        to: body.get("to").and_then(|x| parse_date(x)),
+    };
+    let page = body
+        .get("page")
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(1)
+        .max(1);
+    let per_page = body
+        .get("per_page")
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(10)
+        .clamp(1, MAX_PAGE_SIZE);
+    let (articles, total) = list_articles(&filter, page, per_page).await;
+    json!({"success": true, "articles": articles, "total": total, "page": page, "per_page": per_page}).to_string()
+}
+
+pub async fn get_article_by_id(body: HashMap<&str, &str>) -> String {
+    match get_article(body["id"]).await {
+        Some(article) => json!({"success": true, "article": article}).to_string(),
+        None => json!({"success": false, "message": "That article does not exist."}).to_string(),
+    }
+}
+
+pub async fn attach_article_song(body: HashMap<&str, &str>) -> String {
+    if let Some(mut session) = Session::from_id(body["session"]).await {
+        if session.get("admin").await.unwrap() == "1" {
+            return match attach_song(body["song_id"], body["article_id"]).await {
+                Ok(()) => json!({"success": true}).to_string(),
+                Err(e) => json!({"success": false, "message": e}).to_string(),
+            };
+        }
+    }
+    json!({"success": false}).to_string()
+}
+
+pub async fn detach_article_song(body: HashMap<&str, &str>) -> String {
+    if let Some(mut session) = Session::from_id(body["session"]).await {
+        if session.get("admin").await.unwrap() == "1" {
+            return match detach_song(body["song_id"]).await {
+                Ok(()) => json!({"success": true}).to_string(),
+                Err(e) => json!({"success": false, "message": e}).to_string(),
+            };
+        }
+    }
+    json!({"success": false}).to_string()
+}
+
+pub async fn reorder_article_songs(body: HashMap<&str, &str>) -> String {
+    if let Some(mut session) = Session::from_id(body["session"]).await {
+        if session.get("admin").await.unwrap() == "1" {
+            let song_ids: Vec<i32> = match serde_json::from_str(body["song_ids"]) {
+                Ok(t) => t,
+                Err(e) => return json!({"success": false, "message": e.to_string()}).to_string(),
+            };
+            return match reorder_songs(body["article_id"], &song_ids).await {
+                Ok(()) => json!({"success": true}).to_string(),
+                Err(e) => json!({"success": false, "message": e}).to_string(),
+            };
+        }
+    }
+    json!({"success": false}).to_string()
+}
+
 pub fn get_image_list() -> String {
-    let paths: Vec<String> = fs::read_dir("/srv/http/images/")
+    let paths: Vec<String> = fs::read_dir(IMAGES_DIRECTORY)
         .unwrap()
         .map(|x| x.unwrap().file_name().into_string().unwrap())
         .collect();
     json!({ "images": paths }).to_string()
 }
 
+async fn add_session_rsvps(events: &mut [CalendarEvent], body: &HashMap<&str, &str>) {
+    let session_id = match body.get("session") {
+        Some(t) => t,
+        None => return,
+    };
+    if let Some(mut session) = Session::from_id(session_id).await {
+        if session.get("verified").await.unwrap_or_default() != "1" {
+            return;
+        }
+        let rsvps = get_user_rsvps(&session.get("id").await.unwrap()).await;
+        for event in events {
+            event.rsvp = rsvps
+                .iter()
+                .find(|rsvp| rsvp.event_id == event.id && rsvp.date == event.date)
+                .map(|rsvp| rsvp.status.clone());
+        }
+    }
+}
+
 pub async fn get_calendar_events(body: HashMap<&str, &str>) -> String {
-    let result: Vec<CalendarEvent> = get_like("calendar", "date", body["year_month"])
+    let mut events = match month_range(body["year_month"]) {
+        Some((from, to)) => get_events_between(from, to, body.get("category").copied()).await,
+        None => Vec::new(),
+    };
+    add_session_rsvps(&mut events, &body).await;
+    serde_json::to_string(&events).unwrap()
+}
+
+pub async fn get_calendar_range(body: HashMap<&str, &str>) -> String {
+    let from = parse_date(body.get("from").unwrap_or(&""));
+    let to = parse_date(body.get("to").unwrap_or(&""));
+    match (from, to) {
+        (Some(from), Some(to)) if from <= to => {
+            if (to - from).num_days() > MAX_RANGE_DAYS {
+                return json!({"success": false, "message": format!("Please choose a range of at most {} days.", MAX_RANGE_DAYS)}).to_string();
+            }
+            let mut events = get_events_between(from, to, body.get("category").copied()).await;
+            add_session_rsvps(&mut events, &body).await;
+            json!({"success": true, "events": events}).to_string()
+        }
+        _ => json!({"success": false, "message": "Please choose valid from and to dates."})
+            .to_string(),
+    }
+}
+
+pub async fn get_upcoming_calendar_events(body: HashMap<&str, &str>) -> String {
+    let from = body
+        .get("from")
+        .and_then(|x| parse_date(x))
+        .unwrap_or_else(|| Local::today().naive_local());
+    let limit = body
+        .get("limit")
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(5)
+        .min(MAX_UPCOMING_EVENTS);
+    let mut events = get_upcoming_events(from, limit, body.get("category").copied()).await;
+    add_session_rsvps(&mut events, &body).await;
+    json!({"success": true, "events": events}).to_string()
+}
+
+pub async fn rsvp_event(body: HashMap<&str, &str>) -> String {
+    if let Some(mut session) = Session::from_id(body["session"]).await {
+        if session.get("verified").await.unwrap_or_default() == "1" {
+            if !RSVP_STATUSES.contains(&body["status"]) {
+                return json!({"success": false, "message": "Please respond with yes, no or maybe."}).to_string();
+            }
+            if let Some(event) = get_event(body["event_id"]).await {
+                let date = match body.get("date").and_then(|x| parse_date(x)) {
+                    Some(t) => t,
+                    None => event.naive_date(),
+                };
+                if let Some(occurrence) = event.occurrences(date, date).pop() {
+                    let user_id = session.get("id").await.unwrap();
+                    if let Err(e) =
+                        set_rsvp(event.id, &occurrence.date, &user_id, body["status"]).await
+                    {
+                        return json!({"success": false, "message": e}).to_string();
+                    }
+                    return json!({"success": true, "date": occurrence.date, "status": body["status"]}).to_string();
+                }
+                return json!({"success": false, "message": "This event does not take place on that date."}).to_string();
+            }
+        }
+    }
+    json!({"success": false}).to_string()
+}
+
+pub async fn get_event_attendance(body: HashMap<&str, &str>) -> String {
+    if let Some(mut session) = Session::from_id(body["session"]).await {
+        if session.get("admin").await.unwrap() == "1" {
+            let (attendees, counts) =
+                get_attendance(body["event_id"], body.get("date").copied()).await;
+            return json!({"success": true, "attendees": attendees, "counts": counts}).to_string();
+        }
+    }
+    json!({"success": false}).to_string()
+}
+
+pub async fn import_calendar_file(body: HashMap<&str, &str>) -> String {
+    if let Some(mut session) = Session::from_id(body["session"]).await {
+        if session.get("admin").await.unwrap() == "1" {
+            let apply = body.get("apply") == Some(&"1");
+            let delete_missing = body.get("delete_missing") == Some(&"1");
+            return match import_calendar(body["ics"], apply, delete_missing).await {
+                Ok(preview) => {
+                    json!({"success": true, "applied": apply, "preview": preview}).to_string()
+                }
+                Err(e) => json!({"success": false, "message": e}).to_string(),
+            };
+        }
+    }
+    json!({"success": false}).to_string()
+}
+
+async fn get_calendar_feed(query: HashMap<&str, &str>) -> FileResponse {
+    let events: Vec<CalendarEvent> = get_all_events()
         .await
-        .iter()
-        .map(|x| CalendarEvent {
-            id: from_value(x[0].clone()),
-            title: from_value(x[1].clone()),
-            date: from_value::<NaiveDate>(x[2].clone())
-                .format("%Y-%m-%d")
-                .to_string(),
-            start_time: from_value(x[3].clone()),
-            end_time: from_value(x[4].clone()),
-            notes: from_value(x[6].clone()),
-        })
+        .into_iter()
+        .filter(|event| in_category(event, query.get("category").copied()))
         .collect();
-    serde_json::to_string(&result).unwrap()
+    FileResponse {
+        content_type: "text/calendar; charset=utf-8",
+        cache_control: "no-cache",
+        filename: None,
+        body: calendar_to_ical(&events).into_bytes(),
+    }
+}
+
+async fn get_event_ics(id: &str) -> Option<FileResponse> {
+    let event = get_event(id).await?;
+    Some(FileResponse {
+        content_type: "text/calendar; charset=utf-8",
+        cache_control: "no-cache",
+        filename: Some(format!("olmmcc-event-{}.ics", event.id)),
+        body: calendar_to_ical(&[event]).into_bytes(),
+    })
 }
 
 pub async fn signup(body: HashMap<&str, &str>) -> String {
@@ -160,14 +496,14 @@ pub async fn signup(body: HashMap<&str, &str>) -> String {
         return message(t);
     }
     insert_row(
-        "users",
-        vec!["email", "subscription_policy"],
-        vec![&email, "1"],
+        "accounts",
+        vec!["email", "role", "subscription_policy"],
+        vec![&email, "user", "1"],
     )
     .await
     .unwrap();
-    let mut session = Session::new(30, 100).await;
-    match refresh_user_session(&mut session, "email", email.clone(), "0").await {
+    let mut session = new_session(&body).await;
+    match refresh_account_session(&mut session, "email", email.clone(), false, None).await {
         Some(t) => message(&t),
         None => send_login_email(&mut session).await,
     }
@@ -175,8 +511,8 @@ pub async fn signup(body: HashMap<&str, &str>) -> String {
 
 pub async fn login(body: HashMap<&str, &str>) -> String {
     let email = body["email"].to_lowercase();
-    let mut session = Session::new(30, 100).await;
-    match refresh_user_session(&mut session, "email", email.clone(), "0").await {
+    let mut session = new_session(&body).await;
+    match refresh_account_session(&mut session, "email", email.clone(), false, None).await {
         Some(t) => message(&t),
         None => send_login_email(&mut session).await,
     }
@@ -188,111 +524,90 @@ async fn send_login_email(session: &mut Session) -> String {
     session
         .set("verification_code", verification_code.clone())
         .await;
-    let access_token = get_access_token().await.unwrap();
-    let body = format!("Hello,\r\nTo verify your identity, please copy this code and return to OLMMCC's website: {}\r\n\r\nThis message was sent by the OLMMCC automated system. If you received it in error please contact justus@olmmcc.tk", verification_code);
-    gmail::send_email(
+    let message = render_template(
+        "login",
+        &[("code", verification_code.as_str())],
         vec![email.clone()],
-        "Verify Your Identity",
-        &body,
-        &access_token,
     )
     .await;
-    json!({"session" : session.get_id(), "email": email}).to_string()
+    queue_mail(message).await;
+    json!({
+        "session": session.get_id(),
+        "email": email,
+        "csrf_token": session.get("csrf_token").await.unwrap_or_default(),
+        "remember": session.get("remember").await.unwrap_or_default() == "1"
+    })
+    .to_string()
 }
 
-pub async fn admin_login(body: HashMap<&str, &str>) -> String {
+pub async fn admin_login(body: HashMap<&str, &str>, client: &Client) -> String {
     let email = body["email"].to_lowercase();
-    let mut session = Session::new(30, 100).await;
-    match refresh_admin_session(&mut session, "email", email, Some(body["password"])).await {
+    let mut session = new_session(&body).await;
+    match refresh_account_session(&mut session, "email", email, false, Some(body["password"])).await
+    {
         Some(t) => message(&t),
-        None => json!({"session" : session.get_id()}).to_string(),
-    }
-}
-
-async fn refresh_user_session(
-    session: &mut Session,
-    key: &str,
-    value: String,
-    verified: &str,
-) -> Option<String> {
-    session.clear().await;
-    let users = get_like("users", key, &value).await;
-    if let Some(user) = users.iter().next() {
-        session
-            .set("id", from_value::<i32>(user[1].clone()).to_string())
-            .await;
-        if verified == "1" {
-            session
-                .set("verified", "1".to_string())
-                .await
-                .set("email", from_value(user[0].clone()))
-                .await
-                .set("admin", 0.to_string())
-                .await
-                .set(
-                    "subscription_policy",
-                    from_value::<i32>(user[2].clone()).to_string(),
-                )
-                .await;
-            None
-        } else {
-            session
-                .set("verified", "0".to_string())
-                .await
-                .set("not_verified_email", from_value(user[0].clone()))
-                .await;
-            None
-        }
-    } else {
-        let admin = get_like("admin", key, &value).await;
-        if let Some(admin) = admin.iter().next() {
-            session
-                .set("id", from_value::<i32>(admin[2].clone()).to_string())
-                .await
-                .set("not_verified_admin", 1.to_string())
-                .await
-                .set("verified", "0".to_string())
-                .await
-                .set("not_verified_email", from_value(admin[0].clone()))
-                .await;
-            None
-        } else {
-            Some("This email address is not registered. Please create a new account.".to_string())
+        None => {
+            let expiry = track_session(&mut session, client).await;
+            json!({
+                "session": session.get_id(),
+                "csrf_token": session.get("csrf_token").await.unwrap_or_default(),
+                "remember": session.get("remember").await.unwrap_or_default() == "1",
+                "expiry": expiry
+            })
+            .to_string()
         }
     }
 }
 
-async fn refresh_admin_session(
+async fn refresh_account_session(
     session: &mut Session,
     key: &str,
     value: String,
+    verified: bool,
     password: Option<&str>,
 ) -> Option<String> {
-    session.clear().await;
-    let users = get_like("admin", key, &value).await;
-    if let Some(user) = users.iter().next() {
-        if let Some(p) = password {
-            if !hash_match(p, &from_value::<String>(user[1].clone())) {
-                return Some("Wrong password, please try again.".to_string());
-            }
+    clear_session(session).await;
+    let account = match find_account(key, &value).await {
+        Some(t) => t,
+        None => {
+            return Some(
+                "This email address is not registered. Please create a new account.".to_string(),
+            )
         }
+    };
+    if let Some(p) = password {
+        if !account.is_admin() {
+            return Some("This account is not an administrator account.".to_string());
+        }
+        if !hash_match(p, &account.password) {
+            return Some("Wrong password, please try again.".to_string());
+        }
+    }
+    session.set("id", account.id.to_string()).await;
+    if verified || password.is_some() {
         session
-            .set("id", from_value::<i32>(user[2].clone()).to_string())
-            .await;
-        session
-            .set("email", from_value(user[0].clone()))
+            .set("verified", "1".to_string())
+            .await
+            .set("email", account.email.clone())
             .await
-            .set("admin", 1.to_string())
+            .set(
+                "admin",
+                if account.is_admin() { "1" } else { "0" }.to_string(),
+            )
             .await
             .set(
                 "subscription_policy",
-                from_value::<i32>(user[3].clone()).to_string(),
+                account.subscription_policy.to_string(),
             )
             .await;
-        None
     } else {
-        Some("This account is not an administrator account.".to_string())
+        session
+            .set("verified", "0".to_string())
+            .await
+            .set("not_verified_email", account.email.clone())
+            .await;
     }
+    None
 }
 
 pub async fn get_account(body: HashMap<&str, &str>) -> String {
@@ -310,6 +625,9 @@ pub async fn get_account(body: HashMap<&str, &str>) -> String {
                     );
                 }
             }
+            if let Some(t) = session.get("csrf_token").await {
+                map.insert("csrf_token".to_string(), Value::String(t));
+            }
             return serde_json::to_string(&map).unwrap();
         }
     }
@@ -317,21 +635,74 @@ pub async fn get_account(body: HashMap<&str, &str>) -> String {
 }
 
 pub async fn kill_session(body: HashMap<&str, &str>) -> String {
+    revoke_session(body["session"]).await;
+    json!({}).to_string()
+}
+
+pub async fn session_expiry(body: HashMap<&str, &str>) -> String {
+    if let Some(expiry) = get_session_expiry(body.get("session").unwrap_or(&"")).await {
+        return json!({"success": true, "expiry": expiry}).to_string();
+    }
+    json!({"success": false}).to_string()
+}
+
+async fn get_session_account(session: &mut Session, body: &HashMap<&str, &str>) -> Option<String> {
+    if session.get("admin").await.unwrap_or_default() == "1" {
+        if let Some(target_id) = body.get("target_id") {
+            return Some(target_id.to_string());
+        }
+    }
+    if session.get("verified").await.unwrap_or_default() == "1" {
+        session.get("id").await
+    } else {
+        None
+    }
+}
+
+pub async fn get_active_sessions(body: HashMap<&str, &str>) -> String {
     if let Some(mut session) = Session::from_id(body["session"]).await {
-        session.delete().await;
+        if let Some(id) = get_session_account(&mut session, &body).await {
+            let sessions = list_sessions(&id, &session.get_id()).await;
+            return json!({"success": true, "sessions": sessions}).to_string();
+        }
     }
-    json!({}).to_string()
+    json!({"success": false}).to_string()
+}
+
+pub async fn revoke_active_session(body: HashMap<&str, &str>) -> String {
+    if let Some(mut session) = Session::from_id(body["session"]).await {
+        if let Some(id) = get_session_account(&mut session, &body).await {
+            let success = revoke_session_by_id(body["id"], &id).await;
+            return json!({ "success": success }).to_string();
+        }
+    }
+    json!({"success": false}).to_string()
+}
+
+pub async fn revoke_all_sessions(body: HashMap<&str, &str>) -> String {
+    if let Some(mut session) = Session::from_id(body["session"]).await {
+        if let Some(id) = get_session_account(&mut session, &body).await {
+            let current = session.get_id();
+            revoke_account_sessions(&id, Some(current.as_str())).await;
+            return json!({ "success": true }).to_string();
+        }
+    }
+    json!({"success": false}).to_string()
 }
 
 pub async fn refresh(body: HashMap<&str, &str>) -> String {
     if let Some(mut session) = Session::from_id(body["session"]).await {
+        if session.get("verified").await.unwrap_or_default() != "1" {
+            return json!({ "success": true }).to_string();
+        }
         let id = session.get("id").await.unwrap();
-        let verified = &session.get("verified").await.unwrap();
-        refresh_user_session(&mut session, "id", id, verified)
-            .await
-            .unwrap();
+        if let Some(t) = refresh_account_session(&mut session, "id", id, true, None).await {
+            revoke_session(body["session"]).await;
+            return json!({"success": false, "session": "none", "message": t}).to_string();
+        }
+        return json!({ "success": true }).to_string();
     }
-    json!({}).to_string()
+    json!({"success": false, "session": "none"}).to_string()
 }
 
 pub async fn change_subscription(body: HashMap<&str, &str>) -> String {
@@ -345,7 +716,7 @@ pub async fn change_subscription(body: HashMap<&str, &str>) -> String {
         return message(&t);
     }
     change_row_where(
-        "users",
+        "accounts",
         "id",
         &session.get("id").await.unwrap(),
         "subscription_policy",
@@ -365,15 +736,16 @@ async fn queue_change_email(session: &mut Session, new_email: &str) -> String {
         .set("email_change_code", email_change_code.clone())
         .await;
     session.set("new_email", new_email.to_string()).await;
-    let body = format!("Hello,\r\nYou requested a change of your email address to {}. Please copy this code and return to OLMMCC's website: {}\r\n\r\nThis message was sent by the OLMMCC automated system. If you did not make this request please contact justus@olmmcc.tk", new_email, email_change_code);
-    let access_token = get_access_token().await.unwrap();
-    gmail::send_email(
+    let message = render_template(
+        "change_email",
+        &[
+            ("new_email", new_email),
+            ("code", email_change_code.as_str()),
+        ],
         vec![email.clone()],
-        "Verify your Email Change Request",
-        &body,
-        &access_token,
     )
     .await;
+    queue_mail(message).await;
     email
 }
 
@@ -397,14 +769,17 @@ pub async fn change_email(body: HashMap<&str, &str>) -> String {
             if session.get("email_change_code").await.unwrap() == body["code"] {
                 let id = session.get("id").await.unwrap();
                 let new_email = session.get("new_email").await.unwrap();
+                let current = session.get_id();
+                change_row_where("accounts", "id", &id, "email", &new_email).await;
+                revoke_account_sessions(&id, Some(current.as_str())).await;
                 if admin {
-                    change_row_where("admin", "id", &id, "email", &new_email).await;
-                    refresh_admin_session(&mut session, "id", id, None).await;
-                } else {
-                    change_row_where("users", "id", &id, "email", &new_email).await;
-                    refresh_user_session(&mut session, "id", id, "0").await;
+                    refresh_account_session(&mut session, "id", id, true, None).await;
+                    return json!({ "success": true }).to_string();
                 }
-                return json!({ "success": true }).to_string();
+                change_row_where("accounts", "id", &id, "verified", "0").await;
+                refresh_account_session(&mut session, "id", id, false, None).await;
+                send_login_email(&mut session).await;
+                return json!({ "success": true, "email": new_email }).to_string();
             }
         }
         println!(
@@ -429,15 +804,13 @@ async fn queue_delete_email(session: &mut Session) -> String {
     let email = session.get("email").await.unwrap();
     let delete_code = generate_verification_code();
     session.set("delete_code", delete_code.clone()).await;
-    let body = format!("Hello,\r\nYou requested a deletion of your OLMMCC account. Please copy this code and return to OLMMCC's website: {}\r\n\r\nThis message was sent by the OLMMCC automated system. If you did not make this request please contact justus@olmmcc.tk", delete_code);
-    let access_token = get_access_token().await.unwrap();
-    gmail::send_email(
+    let message = render_template(
+        "delete_account",
+        &[("code", delete_code.as_str())],
         vec![email.clone()],
-        "Verify your Account Deletion Request",
-        &body,
-        &access_token,
     )
     .await;
+    queue_mail(message).await;
     email
 }
 
@@ -447,11 +820,8 @@ pub async fn delete_account(body: HashMap<&str, &str>) -> String {
         if admin || session.get("verified").await.unwrap() == "1" {
             if session.get("delete_code").await.unwrap() == body["code"] {
                 let id = session.get("id").await.unwrap();
-                if admin {
-                    delete_row_where("admin", "id", &id).await;
-                } else {
-                    delete_row_where("users", "id", &id).await;
-                }
+                delete_row_where("accounts", "id", &id).await;
+                revoke_account_sessions(&id, None).await;
                 return json!({ "success": true }).to_string();
             }
         }
@@ -459,6 +829,26 @@ pub async fn delete_account(body: HashMap<&str, &str>) -> String {
     json!({"success": false}).to_string()
 }
 
+const HIDDEN_COLUMNS: &[(&str, &str)] = &[
+    ("accounts", "refresh_token"),
+    ("calendar", "uid"),
+    ("outbox", "email"),
+];
+
+fn is_hidden_column(table: &str, column: &str) -> bool {
+    HIDDEN_COLUMNS.contains(&(table, column))
+}
+
+async fn get_hidden_columns(table: &str) -> Vec<usize> {
+    get_column_details(table)
+        .await
+        .iter()
+        .enumerate()
+        .filter(|(_, column)| is_hidden_column(table, &from_value::<String>(column[0].clone())))
+        .map(|(i, _)| i)
+        .collect()
+}
+
 async fn get_column_types(table: &str) -> Vec<String> {
     let mut column_types = Vec::new();
     for column in get_column_details(table).await {
@@ -472,13 +862,20 @@ pub async fn get_database(body: HashMap<&str, &str>) -> String {
         if session.get("admin").await.unwrap() == "1" {
             let mut column_names = Vec::new();
             for column in get_column_details(body["table"]).await {
-                column_names.push(from_value::<String>(column[0].clone()));
+                let name = from_value::<String>(column[0].clone());
+                if !is_hidden_column(body["table"], &name) {
+                    column_names.push(name);
+                }
             }
             let mut processed_rows = Vec::new();
+            let hidden_columns = get_hidden_columns(body["table"]).await;
             let column_types = get_column_types(body["table"]).await;
             for row in get_all_rows(body["table"], true).await {
                 let mut new_row = Vec::new();
                 for i in 0..row.len() {
+                    if hidden_columns.contains(&i) {
+                        continue;
+                    }
                     push_value(
                         &column_types[i],
                         &mut MyValue::from(row[i].clone()),
@@ -487,6 +884,12 @@ pub async fn get_database(body: HashMap<&str, &str>) -> String {
                 }
                 processed_rows.push(new_row);
             }
+            let column_types: Vec<&String> = column_types
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| !hidden_columns.contains(i))
+                .map(|(_, column_type)| column_type)
+                .collect();
             return json!({"success": true, "columns" : column_names, "rows" : processed_rows, "types" : column_types}).to_string();
         }
     }
@@ -519,8 +922,12 @@ pub async fn get_row_titles(body: HashMap<&str, &str>) -> String {
 async fn return_row(table: &str, id: i32) -> Vec<String> {
     let row = get_like(table, "id", &id.to_string()).await[0].clone();
     let mut formatted_row = Vec::new();
+    let hidden_columns = get_hidden_columns(table).await;
     let column_types = get_column_types(table).await;
     for i in 0..row.len() {
+        if hidden_columns.contains(&i) {
+            continue;
+        }
         push_value(
             &column_types[i],
             &mut MyValue::from(row[i].clone()),
@@ -559,18 +966,17 @@ pub async fn move_row_to_start(body: HashMap<&str, &str>) -> String {
 pub async fn delete_row(body: HashMap<&str, &str>) -> String {
     if let Some(mut session) = Session::from_id(body["session"]).await {
         if session.get("admin").await.unwrap() == "1" {
-            if body["table"] == "admin" {
+            if body["table"] == "accounts" {
                 if session.get("id").await.unwrap() == body["id"] {
                     return json!({"success" : false, "authorized" : true, "email": queue_delete_email(&mut session).await}).to_string();
-                } else {
+                } else if is_admin_account(body["id"]).await {
                     return json!({"success" : false, "authorized": false}).to_string();
                 }
-            } else {
-                delete_row_where(body["table"], "id", body["id"]).await;
-                let message = format!("Successfully deleted row {}.", body["id"]);
-                return json!({"success" : true, "message" : message, "id" : body["id"]})
-                    .to_string();
+                revoke_account_sessions(body["id"], None).await;
             }
+            delete_row_where(body["table"], "id", body["id"]).await;
+            let message = format!("Successfully deleted row {}.", body["id"]);
+            return json!({"success" : true, "message" : message, "id" : body["id"]}).to_string();
         }
     }
     json!({}).to_string()
@@ -579,8 +985,24 @@ pub async fn delete_row(body: HashMap<&str, &str>) -> String {
 pub async fn add_row(body: HashMap<&str, &str>) -> String {
     if let Some(mut session) = Session::from_id(body["session"]).await {
         if session.get("admin").await.unwrap() == "1" {
-            let names = serde_json::from_str(body["names"]).unwrap();
-            let values = serde_json::from_str(body["values"]).unwrap();
+            let mut names: Vec<&str> = serde_json::from_str(body["names"]).unwrap();
+            let mut values: Vec<&str> = serde_json::from_str(body["values"]).unwrap();
+            if names
+                .iter()
+                .any(|name| is_hidden_column(body["table"], name))
+            {
+                return json!({"success" : false, "message" : "That column cannot be edited here."}).to_string();
+            }
+            let uid = new_event_uid();
+            if body["table"] == "calendar" {
+                for (name, value) in names.iter().zip(values.iter()) {
+                    if let Some(e) = check_calendar_value(name, value) {
+                        return json!({"success" : false, "message" : e}).to_string();
+                    }
+                }
+                names.push("uid");
+                values.push(&uid);
+            }
             if let Err(e) = insert_row(body["table"], names, values).await {
                 return json!({"success" : false, "message" : e}).to_string();
             } else {
@@ -597,12 +1019,20 @@ pub async fn add_row(body: HashMap<&str, &str>) -> String {
 pub async fn change_row(body: HashMap<&str, &str>) -> String {
     if let Some(mut session) = Session::from_id(body["session"]).await {
         if session.get("admin").await.unwrap() == "1" {
-            if body["table"] == "admin" {
+            if is_hidden_column(body["table"], body["name"]) {
+                return json!({"success" : false, "message" : "That column cannot be edited here."}).to_string();
+            }
+            if body["table"] == "calendar" {
+                if let Some(e) = check_calendar_value(body["name"], body["value"]) {
+                    return json!({"success" : false, "message" : e}).to_string();
+                }
+            }
+            if body["table"] == "accounts" {
                 if session.get("id").await.unwrap() == body["id"] {
                     if body["name"] == "email" {
                         return json!({"success" : false, "authorized" : true, "email": queue_change_email(&mut session, body["value"]).await}).to_string();
                     }
-                } else {
+                } else if is_admin_account(body["id"]).await {
                     return json!({"success" : false, "authorized": false}).to_string();
                 }
             }
@@ -620,16 +1050,12 @@ pub async fn change_row(body: HashMap<&str, &str>) -> String {
 pub async fn get_gmail_auth_url(body: HashMap<&str, &str>) -> String {
     if let Some(mut session) = Session::from_id(body["session"]).await {
         if session.get("admin").await.unwrap() == "1" {
-            let mut file = File::open("/home/justus/client_secret.json").unwrap();
-            let mut contents = String::new();
-            file.read_to_string(&mut contents).unwrap();
-            let json: Value = serde_json::from_str(&contents).unwrap();
-            return json!({
-                "url": &format!(
-                "https://accounts.google.com/o/oauth2/v2/auth?scope=https://mail.google.com/&include_granted_scopes=true&prompt=consent&redirect_uri=https://www.olmmcc.tk/admin/email/&response_type=code&client_id={}&access_type=offline", 
-                json["client_id"].as_str().unwrap(),
-            )
-            }).to_string();
+            let state = generate_csrf_token();
+            session.set("gmail_oauth_state", state.clone()).await;
+            return match auth_url(&state) {
+                Ok(url) => json!({ "url": url }).to_string(),
+                Err(e) => json!({"url": "", "message": e}).to_string(),
+            };
         }
     }
     json!({"url": ""}).to_string()
@@ -638,59 +1064,59 @@ pub async fn get_gmail_auth_url(body: HashMap<&str, &str>) -> String {
 pub async fn send_gmail_code(body: HashMap<&str, &str>) -> String {
     if let Some(mut session) = Session::from_id(body["session"]).await {
         if session.get("admin").await.unwrap() == "1" {
-            let refresh_token = gmail::get_refresh_token(body["code"]);
-            let email = &session.get("email").await.unwrap();
-            if row_exists("admin", "email", email).await {
-                change_row_where(
-                    "admin",
-                    "email",
-                    email,
-                    "refresh_token",
-                    &refresh_token.await,
-                )
-                .await;
-            } else {
-                insert_row(
-                    "admin",
-                    vec!["email", "refresh_token"],
-                    vec![email, &refresh_token.await],
-                )
-                .await
-                .unwrap();
+            let state = session.get("gmail_oauth_state").await.unwrap_or_default();
+            session.set("gmail_oauth_state", String::new()).await;
+            if state.is_empty() || body.get("state") != Some(&state.as_str()) {
+                return json!({"success": false, "message": "This Gmail authorization request has expired. Please try again."}).to_string();
             }
+            return match authorize(&session.get("id").await.unwrap(), body["code"]).await {
+                Ok(sender) => json!({"success": true, "sender": sender}).to_string(),
+                Err(e) => json!({"success": false, "message": e}).to_string(),
+            };
         }
     }
-    json!({}).to_string()
+    json!({"success": false}).to_string()
 }
 
-pub async fn is_gmail_working(body: HashMap<&str, &str>) -> String {
+pub async fn get_gmail_senders(body: HashMap<&str, &str>) -> String {
     if let Some(mut session) = Session::from_id(body["session"]).await {
         if session.get("admin").await.unwrap() == "1" {
-            if get_refresh_token().await.is_some() {
-                if get_access_token().await.is_some() {
-                    return json!({"working": true}).to_string();
-                }
-            }
+            return json!({"success": true, "senders": get_authorizations().await}).to_string();
         }
     }
-    json!({"working": false}).to_string()
+    json!({"success": false}).to_string()
 }
 
-async fn get_refresh_token() -> Option<MyValue> {
-    let mut return_token = None;
-    for row in get_all_rows("admin", false).await {
-        let token = row[4].clone();
-        if from_value::<String>(token.clone()) != String::new() {
-            return_token = Some(MyValue::from(token));
-            break;
+pub async fn set_gmail_sender(body: HashMap<&str, &str>) -> String {
+    if let Some(mut session) = Session::from_id(body["session"]).await {
+        if session.get("admin").await.unwrap() == "1" && set_sender(body["id"]).await {
+            return json!({"success": true}).to_string();
         }
     }
-    return_token
+    json!({"success": false}).to_string()
 }
 
-async fn get_access_token() -> Option<String> {
-    let refresh_token = get_refresh_token();
-    gmail::get_access_token(&from_value::<String>(refresh_token.await.unwrap().get())).await
+pub async fn is_gmail_working(body: HashMap<&str, &str>) -> String {
+    if let Some(mut session) = Session::from_id(body["session"]).await {
+        if session.get("admin").await.unwrap() == "1" {
+            let sender = match get_sender().await {
+                Some(t) => t,
+                None => {
+                    return json!({"working": false, "message": "No admin has authorized Gmail to send email."}).to_string()
+                }
+            };
+            let result = get_access_token(&sender).await;
+            let sender = get_sender().await.unwrap_or(sender);
+            return json!({
+                "working": result.is_ok(),
+                "message": result.err(),
+                "token_age": sender.token_age(),
+                "sender": sender,
+            })
+            .to_string();
+        }
+    }
+    json!({"working": false}).to_string()
 }
 
 fn generate_verification_code() -> String {
@@ -713,45 +1139,408 @@ pub async fn hash_password(body: HashMap<&str, &str>) -> String {
     json!({}).to_string()
 }
 
-pub async fn verify_account(body: HashMap<&str, &str>) -> String {
+pub async fn verify_account(body: HashMap<&str, &str>, client: &Client) -> String {
     if let Some(mut session) = Session::from_id(body["session"]).await {
         if session.get("verified").await.unwrap() == "0" {
             if session.get("verification_code").await.unwrap() == body["code"] {
                 let email = session.get("not_verified_email").await.unwrap();
-                if session.get("not_verified_admin").await.unwrap_or_default() == "1" {
-                    refresh_admin_session(&mut session, "email", email, None).await;
-                } else {
-                    refresh_user_session(&mut session, "email", email, "1").await;
+                if let Some(t) =
+                    refresh_account_session(&mut session, "email", email, true, None).await
+                {
+                    return json!({"success": false, "message": t}).to_string();
                 }
-                return json!({ "success": true }).to_string();
+                change_row_where(
+                    "accounts",
+                    "id",
+                    &session.get("id").await.unwrap(),
+                    "verified",
+                    "1",
+                )
+                .await;
+                let expiry = track_session(&mut session, client).await;
+                return json!({ "success": true, "expiry": expiry }).to_string();
             }
         }
     }
     json!({"success": false}).to_string()
 }
 
+fn compose_email(body: &HashMap<&str, &str>, emails: Vec<String>) -> Result<Email, String> {
+    let html = body.get("html").filter(|x| !x.is_empty());
+    let text = match (body.get("body").filter(|x| !x.is_empty()), html) {
+        (Some(t), _) => t.to_string(),
+        (None, Some(t)) => html_to_text(t),
+        (None, None) => String::new(),
+    };
+    let subject = body
+        .get("subject")
+        .filter(|x| !x.is_empty())
+        .ok_or_else(|| "Please enter a subject for this email.".to_string())?;
+    let mut email = Email::new(emails, subject, &text);
+    email.html = html.map(|x| x.to_string());
+    if let Some(t) = body.get("inline_images") {
+        let names: Vec<String> = serde_json::from_str(t).map_err(|e| e.to_string())?;
+        for name in names {
+            email
+                .inline_images
+                .push(Attachment::from_file(IMAGES_DIRECTORY, &name)?);
+        }
+    }
+    if let Some(t) = body.get("attachments") {
+        let attachments: Vec<UploadedAttachment> =
+            serde_json::from_str(t).map_err(|e| e.to_string())?;
+        for attachment in attachments {
+            email.attachments.push(Attachment {
+                name: attachment.name,
+                content_type: attachment.content_type,
+                data: base64::decode(&attachment.data).map_err(|e| e.to_string())?,
+            });
+        }
+    }
+    Ok(email)
+}
+
+const PERSONAL_VARIABLES: &[&str] = &["email", "unsubscribe_link"];
+
+fn personal_email(
+    email: &Email,
+    account: &Account,
+    recipient_id: i32,
+    track_opens: bool,
+) -> Result<Email, String> {
+    let id = account.id.to_string();
+    let link = unsubscribe_link(&id)?;
+    let mut personal_email = personalize(
+        email,
+        &[
+            ("email", account.email.as_str()),
+            ("unsubscribe_link", link.as_str()),
+        ],
+    );
+    personal_email.to = vec![account.email.clone()];
+    add_unsubscribe(&mut personal_email, &id)?;
+    if track_opens {
+        add_tracking_pixel(&mut personal_email, recipient_id)?;
+    }
+    Ok(personal_email)
+}
+
+async fn deliver_email(body: &HashMap<&str, &str>) -> Value {
+    let email = match compose_email(body, Vec::new()) {
+        Ok(t) => t,
+        Err(e) => return json!({"success": false, "message": e}),
+    };
+    let sender = body.get("sender").unwrap_or(&"");
+    let recipients = body.get("recipients").copied().unwrap_or_default();
+    if !is_audience(recipients) {
+        let recipient = match body.get("recipient").filter(|x| !x.is_empty()) {
+            Some(t) => t.to_string(),
+            None => {
+                return json!({"success": false, "message": "Please choose who to send this email to."})
+            }
+        };
+        let campaign_id = create_campaign(&email, sender, "", "individual", false).await;
+        let recipient_id = add_campaign_recipient(campaign_id, 0, &recipient).await;
+        let mut email = personalize(&email, &[("email", recipient.as_str())]);
+        email.to = vec![recipient];
+        let outbox_id = queue_campaign_mail(email, unix_time(), campaign_id).await;
+        set_recipient_outbox(&[recipient_id], outbox_id).await;
+        finish_campaign(campaign_id, 1).await;
+        return json!({ "success": true, "count": 1, "batches": 1, "campaign_id": campaign_id });
+    }
+    let accounts = get_audience(recipients).await;
+    if accounts.is_empty() {
+        return json!({"success": false, "message": "There is nobody to send this email to."});
+    }
+    let delivery = body
+        .get("delivery")
+        .map(|x| x.to_string())
+        .unwrap_or_else(config::mail_delivery);
+    if delivery != "bcc" && delivery != "individual" {
+        return json!({"success": false, "message": "Delivery must be either individual or bcc."});
+    }
+    if delivery == "bcc" && is_personalized(&email, PERSONAL_VARIABLES) {
+        return json!({"success": false, "message": "Personalized placeholders cannot be used when sending by BCC."});
+    }
+    if delivery == "individual" {
+        if let Err(e) = config::unsubscribe_secret() {
+            return json!({"success": false, "message": e});
+        }
+    }
+    let track_opens =
+        delivery == "individual" && email.html.is_some() && body.get("track_opens") == Some(&"1");
+    let batch_size = body
+        .get("batch_size")
+        .and_then(|x| x.parse::<usize>().ok())
+        .filter(|x| *x > 0)
+        .unwrap_or_else(config::mail_batch_size);
+    let now = unix_time();
+    let batches = accounts.chunks(batch_size);
+    let count = batches.len();
+    let campaign_id = create_campaign(&email, sender, recipients, &delivery, track_opens).await;
+    for (i, batch) in batches.enumerate() {
+        let send_at = now + i as i64 * config::mail_batch_interval();
+        if delivery == "bcc" {
+            let mut recipient_ids = Vec::new();
+            for account in batch {
+                recipient_ids
+                    .push(add_campaign_recipient(campaign_id, account.id, &account.email).await);
+            }
+            let mut batch_email = email.clone();
+            batch_email.to = vec![config::mail_from()];
+            batch_email.bcc = batch.iter().map(|x| x.email.clone()).collect();
+            add_bulk_unsubscribe(&mut batch_email);
+            let outbox_id = queue_campaign_mail(batch_email, send_at, campaign_id).await;
+            set_recipient_outbox(&recipient_ids, outbox_id).await;
+        } else {
+            for account in batch {
+                let recipient_id =
+                    add_campaign_recipient(campaign_id, account.id, &account.email).await;
+                let personal_email =
+                    match personal_email(&email, account, recipient_id, track_opens) {
+                        Ok(t) => t,
+                        Err(e) => return json!({"success": false, "message": e}),
+                    };
+                let outbox_id = queue_campaign_mail(personal_email, send_at, campaign_id).await;
+                set_recipient_outbox(&[recipient_id], outbox_id).await;
+            }
+        }
+    }
+    finish_campaign(campaign_id, accounts.len()).await;
+    json!({ "success": true, "count": accounts.len(), "batches": count, "campaign_id": campaign_id })
+}
+
 pub async fn send_email(body: HashMap<&str, &str>) -> String {
     if let Some(mut session) = Session::from_id(body["session"]).await {
         if session.get("admin").await.unwrap() == "1" {
-            let mut emails = vec![];
-            if body["recipients"] == "all_users" {
-                for row in get_some("users", "email").await {
-                    emails.push(from_value::<String>(row[0].clone()));
+            let sender = session.get("email").await.unwrap_or_default();
+            let mut body = body;
+            body.insert("sender", &sender);
+            return deliver_email(&body).await.to_string();
+        }
+    }
+    json!({ "success": false }).to_string()
+}
+
+pub async fn unsubscribe(body: HashMap<&str, &str>) -> String {
+    if let Err(e) = config::unsubscribe_secret() {
+        return json!({"success": false, "message": e}).to_string();
+    }
+    let id = body.get("id").unwrap_or(&"");
+    if verify_unsubscribe_token(id, body.get("token").unwrap_or(&""))
+        && row_exists("accounts", "id", id).await
+    {
+        change_row_where("accounts", "id", id, "subscription_policy", "0").await;
+        return json!({"success": true, "message": "You are now unsubscribed from receiving emails."}).to_string();
+    }
+    json!({"success": false, "message": "This unsubscribe link is not valid."}).to_string()
+}
+
+pub async fn count_recipients(body: HashMap<&str, &str>) -> String {
+    if let Some(mut session) = Session::from_id(body["session"]).await {
+        if session.get("admin").await.unwrap() == "1" && is_audience(body["recipients"]) {
+            let count = get_audience(body["recipients"]).await.len();
+            return json!({"success": true, "count": count}).to_string();
+        }
+    }
+    json!({ "success": false }).to_string()
+}
+
+pub async fn get_outbox(body: HashMap<&str, &str>) -> String {
+    if let Some(mut session) = Session::from_id(body["session"]).await {
+        if session.get("admin").await.unwrap() == "1" {
+            let status = body.get("status").unwrap_or(&"failed");
+            let messages = get_outbox_messages(status).await;
+            return json!({"success": true, "messages": messages}).to_string();
+        }
+    }
+    json!({ "success": false }).to_string()
+}
+
+pub async fn resend_email(body: HashMap<&str, &str>) -> String {
+    if let Some(mut session) = Session::from_id(body["session"]).await {
+        if session.get("admin").await.unwrap() == "1" && resend_message(body["id"]).await {
+            let message = format!("Message {} will be sent again shortly.", body["id"]);
+            return json!({"success": true, "message": message}).to_string();
+        }
+    }
+    json!({ "success": false }).to_string()
+}
+
+pub async fn get_email_campaigns(body: HashMap<&str, &str>) -> String {
+    if let Some(mut session) = Session::from_id(body["session"]).await {
+        if session.get("admin").await.unwrap() == "1" {
+            return json!({"success": true, "campaigns": get_campaigns().await}).to_string();
+        }
+    }
+    json!({ "success": false }).to_string()
+}
+
+pub async fn get_email_campaign(body: HashMap<&str, &str>) -> String {
+    if let Some(mut session) = Session::from_id(body["session"]).await {
+        if session.get("admin").await.unwrap() == "1" {
+            if let Some((campaign, recipients)) = get_campaign(body["id"]).await {
+                return json!({"success": true, "campaign": campaign, "recipients": recipients})
+                    .to_string();
+            }
+        }
+    }
+    json!({ "success": false }).to_string()
+}
+
+async fn track_open(query: HashMap<&str, &str>) -> FileResponse {
+    record_open(
+        query.get("id").unwrap_or(&""),
+        query.get("token").unwrap_or(&""),
+    )
+    .await;
+    FileResponse {
+        content_type: "image/gif",
+        cache_control: "no-store",
+        filename: None,
+        body: TRACKING_PIXEL.to_vec(),
+    }
+}
+
+pub async fn save_newsletter_draft(body: HashMap<&str, &str>) -> String {
+    if let Some(mut session) = Session::from_id(body["session"]).await {
+        if session.get("admin").await.unwrap() == "1" {
+            let sender = session.get("email").await.unwrap_or_default();
+            let mut body = body;
+            body.insert("sender", &sender);
+            return match save_newsletter(body.get("id").copied(), &body).await {
+                Ok(id) => json!({"success": true, "id": id}).to_string(),
+                Err(e) => json!({"success": false, "message": e}).to_string(),
+            };
+        }
+    }
+    json!({ "success": false }).to_string()
+}
+
+pub async fn list_newsletters(body: HashMap<&str, &str>) -> String {
+    if let Some(mut session) = Session::from_id(body["session"]).await {
+        if session.get("admin").await.unwrap() == "1" {
+            let newsletters = get_newsletters(body.get("status").copied()).await;
+            return json!({"success": true, "newsletters": newsletters}).to_string();
+        }
+    }
+    json!({ "success": false }).to_string()
+}
+
+pub async fn test_newsletter(body: HashMap<&str, &str>) -> String {
+    if let Some(mut session) = Session::from_id(body["session"]).await {
+        if session.get("admin").await.unwrap() == "1" {
+            let account = find_account("id", &session.get("id").await.unwrap_or_default()).await;
+            if let (Some(newsletter), Some(account)) = (get_newsletter(body["id"]).await, account) {
+                let mut message = newsletter.message();
+                let subject = format!("[Test] {}", message.get("subject").unwrap_or(&""));
+                message.insert("subject", &subject);
+                // Test copies are queued directly so they are not recorded as campaigns.
+                let email = match compose_email(&message, Vec::new()) {
+                    Ok(t) => t,
+                    Err(e) => return json!({"success": false, "message": e}).to_string(),
+                };
+                let mut email = personalize(&email, &[("email", account.email.as_str())]);
+                email.to = vec![account.email.clone()];
+                queue_mail(email).await;
+                let message = format!("A test copy was sent to {}.", account.email);
+                return json!({"success": true, "message": message}).to_string();
+            }
+        }
+    }
+    json!({ "success": false }).to_string()
+}
+
+pub async fn schedule_newsletter_send(body: HashMap<&str, &str>) -> String {
+    if let Some(mut session) = Session::from_id(body["session"]).await {
+        if session.get("admin").await.unwrap() == "1" {
+            let send_at = match parse_send_at(body.get("send_at").unwrap_or(&"")) {
+                Some(t) => t,
+                None => return json!({"success": false, "message": "Please choose a valid date and time to send this newsletter."}).to_string(),
+            };
+            if let Some(newsletter) = get_newsletter(body["id"]).await {
+                if let Err(e) = compose_email(&newsletter.message(), Vec::new()) {
+                    return json!({"success": false, "message": e}).to_string();
                 }
-                for row in get_some("admin", "email").await {
-                    emails.push(from_value::<String>(row[0].clone()));
+            }
+            return match schedule_newsletter(body["id"], send_at).await {
+                Ok(()) => json!({"success": true, "send_at": send_at}).to_string(),
+                Err(e) => json!({"success": false, "message": e}).to_string(),
+            };
+        }
+    }
+    json!({ "success": false }).to_string()
+}
+
+pub async fn cancel_newsletter_send(body: HashMap<&str, &str>) -> String {
+    if let Some(mut session) = Session::from_id(body["session"]).await {
+        if session.get("admin").await.unwrap() == "1" && cancel_newsletter(body["id"]).await {
+            return json!({"success": true, "message": "The newsletter was moved back to drafts."})
+                .to_string();
+        }
+    }
+    json!({ "success": false }).to_string()
+}
+
+pub async fn get_email_templates(body: HashMap<&str, &str>) -> String {
+    if let Some(mut session) = Session::from_id(body["session"]).await {
+        if session.get("admin").await.unwrap() == "1" {
+            return json!({"success": true, "templates": get_templates().await}).to_string();
+        }
+    }
+    json!({ "success": false }).to_string()
+}
+
+pub async fn change_email_template(body: HashMap<&str, &str>) -> String {
+    if let Some(mut session) = Session::from_id(body["session"]).await {
+        if session.get("admin").await.unwrap() == "1" {
+            if !is_template(body["name"]) {
+                return json!({"success": false, "message": "There is no email template with that name."}).to_string();
+            }
+            save_template(body["name"], body["subject"], body["text"], body["html"]).await;
+            let message = format!("Successfully updated the {} template.", body["name"]);
+            return json!({"success": true, "message": message}).to_string();
+        }
+    }
+    json!({ "success": false }).to_string()
+}
+
+pub async fn reset_email_template(body: HashMap<&str, &str>) -> String {
+    if let Some(mut session) = Session::from_id(body["session"]).await {
+        if session.get("admin").await.unwrap() == "1" {
+            reset_template(body["name"]).await;
+            return json!({"success": true, "template": get_template(body["name"]).await})
+                .to_string();
+        }
+    }
+    json!({ "success": false }).to_string()
+}
+
+pub async fn preview_email_template(body: HashMap<&str, &str>) -> String {
+    if let Some(mut session) = Session::from_id(body["session"]).await {
+        if session.get("admin").await.unwrap() == "1" {
+            if let Some(mut template) = get_template(body["name"]).await {
+                template.edit(body.get("subject"), body.get("text"), body.get("html"));
+                let variables: HashMap<String, String> = body
+                    .get("variables")
+                    .and_then(|x| serde_json::from_str(x).ok())
+                    .unwrap_or_default();
+                let mut preview_variables = vec![
+                    ("code", "A1B2C3D4E5F6G7H8"),
+                    ("new_email", "new.address@example.com"),
+                ];
+                for (name, value) in &variables {
+                    preview_variables.push((name.as_str(), value.as_str()));
                 }
-            } else {
-                emails.push(body["recipient"].to_string());
+                let email = render(&template, &preview_variables, Vec::new()).await;
+                return json!({
+                    "success": true,
+                    "subject": email.subject,
+                    "text": email.body,
+                    "html": email.html
+                })
+                .to_string();
             }
-            gmail::send_email(
-                emails,
-                body["subject"],
-                body["body"],
-                get_access_token().await.unwrap().as_str(),
-            )
-            .await;
-            return json!({ "success": true }).to_string();
         }
     }
     json!({ "success": false }).to_string()
@@ -0,0 +1,200 @@
+use async_trait::async_trait;
+use hyper::{Body, Client, Request};
+use hyper_tls::HttpsConnector;
+use lettre::smtp::authentication::Credentials;
+use lettre::{EmailAddress, Envelope, SendableEmail, SmtpClient, Transport};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::config::*;
+use crate::gmail_auth::*;
+use crate::mime::*;
+use crate::{generate_verification_code, unix_time};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Email {
+    pub to: Vec<String>,
+    #[serde(default)]
+    pub bcc: Vec<String>,
+    pub subject: String,
+    pub body: String,
+    pub html: Option<String>,
+    #[serde(default)]
+    pub inline_images: Vec<Attachment>,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+}
+
+impl Email {
+    pub fn new(to: Vec<String>, subject: &str, body: &str) -> Email {
+        Email {
+            to,
+            bcc: Vec::new(),
+            subject: subject.to_string(),
+            body: body.to_string(),
+            html: None,
+            inline_images: Vec::new(),
+            attachments: Vec::new(),
+            headers: Vec::new(),
+        }
+    }
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: &Email) -> Result<(), String>;
+}
+
+pub struct GmailMailer;
+
+#[async_trait]
+impl Mailer for GmailMailer {
+    async fn send(&self, email: &Email) -> Result<(), String> {
+        let sender = get_sender()
+            .await
+            .ok_or_else(|| "Gmail is not authorized to send email.".to_string())?;
+        let access_token = get_access_token(&sender).await?;
+        let message = bcc_header(email) + &build_message(email, sender.sender());
+        let raw = base64::encode_config(message, base64::URL_SAFE);
+        let request = Request::post("https://gmail.googleapis.com/gmail/v1/users/me/messages/send")
+            .header("Authorization", format!("Bearer {}", access_token))
+            .header("Content-Type", "application/json")
+            .body(Body::from(json!({ "raw": raw }).to_string()))
+            .map_err(|e| e.to_string())?;
+        let response = Client::builder()
+            .build::<_, Body>(HttpsConnector::new())
+            .request(request)
+            .await
+            .map_err(|e| e.to_string())?;
+        if response.status().is_success() {
+            record_success(sender.account_id()).await;
+            Ok(())
+        } else {
+            let error = format!("Gmail responded with {}.", response.status());
+            record_failure(sender.account_id(), &error).await;
+            Err(error)
+        }
+    }
+}
+
+pub struct SmtpMailer {
+    host: String,
+    username: String,
+    password: String,
+    from: String,
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, email: &Email) -> Result<(), String> {
+        let to = email
+            .to
+            .iter()
+            .chain(email.bcc.iter())
+            .map(|x| EmailAddress::new(x.clone()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        let from = EmailAddress::new(self.from.clone()).map_err(|e| e.to_string())?;
+        let envelope = Envelope::new(Some(from), to).map_err(|e| e.to_string())?;
+        let message = SendableEmail::new(
+            envelope,
+            generate_verification_code(),
+            build_message(email, &self.from).into_bytes(),
+        );
+        let client = SmtpClient::new_simple(&self.host)
+            .map_err(|e| e.to_string())?
+            .credentials(Credentials::new(
+                self.username.clone(),
+                self.password.clone(),
+            ));
+        tokio::task::spawn_blocking(move || {
+            client
+                .transport()
+                .send(message)
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        })
+        .await
+        .map_err(|e| e.to_string())?
+    }
+}
+
+pub struct FileMailer {
+    directory: PathBuf,
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: &Email) -> Result<(), String> {
+        fs::create_dir_all(&self.directory).map_err(|e| e.to_string())?;
+        let path = self.directory.join(format!(
+            "{}-{}.eml",
+            unix_time(),
+            generate_verification_code()
+        ));
+        let message = bcc_header(email) + &build_message(email, &mail_from());
+        fs::write(path, message).map_err(|e| e.to_string())
+    }
+}
+
+static MEMORY_OUTBOX: Mutex<Vec<Email>> = Mutex::new(Vec::new());
+
+pub struct MemoryMailer;
+
+#[async_trait]
+impl Mailer for MemoryMailer {
+    async fn send(&self, email: &Email) -> Result<(), String> {
+        MEMORY_OUTBOX.lock().unwrap().push(email.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub fn memory_outbox() -> Vec<Email> {
+    MEMORY_OUTBOX.lock().unwrap().clone()
+}
+
+pub fn mailer() -> Box<dyn Mailer> {
+    match mail_transport().as_str() {
+        "smtp" => Box::new(SmtpMailer {
+            host: smtp_host(),
+            username: smtp_username(),
+            password: smtp_password(),
+            from: mail_from(),
+        }),
+        "file" => Box::new(FileMailer {
+            directory: mail_directory(),
+        }),
+        "memory" => Box::new(MemoryMailer),
+        _ => Box::new(GmailMailer),
+    }
+}
+
+pub async fn send_mail(email: Email) -> Result<(), String> {
+    let result = mailer().send(&email).await;
+    if let Err(e) = &result {
+        eprintln!("Failed to send \"{}\": {}", email.subject, e);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn memory_mailer_records_sent_mail() {
+        let email = Email::new(vec!["member@example.com".to_string()], "Hello", "Body");
+        MemoryMailer.send(&email).await.unwrap();
+        let sent = memory_outbox();
+        assert!(sent
+            .iter()
+            .any(|x| x.to == email.to && x.subject == "Hello" && x.body == "Body"));
+    }
+}
@@ -0,0 +1,297 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use std::fs;
+use std::path::Path;
+
+use crate::generate_verification_code;
+use crate::mailer::Email;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Attachment {
+    pub name: String,
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+impl Attachment {
+    pub fn from_file(directory: &str, name: &str) -> Result<Attachment, String> {
+        if name.contains('/') || name.contains('\\') || name.starts_with('.') {
+            return Err(format!("{} is not a valid file name.", name));
+        }
+        let data = fs::read(Path::new(directory).join(name)).map_err(|e| e.to_string())?;
+        Ok(Attachment {
+            name: name.to_string(),
+            content_type: content_type(name).to_string(),
+            data,
+        })
+    }
+}
+
+fn content_type(name: &str) -> &'static str {
+    match name
+        .rsplit('.')
+        .next()
+        .unwrap_or_default()
+        .to_lowercase()
+        .as_str()
+    {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "pdf" => "application/pdf",
+        "txt" => "text/plain",
+        _ => "application/octet-stream",
+    }
+}
+
+fn single_line(value: &str) -> String {
+    value.replace(['\r', '\n'], " ")
+}
+
+fn encode_header(value: &str) -> String {
+    let value = single_line(value);
+    if value.is_ascii() {
+        value.to_string()
+    } else {
+        format!("=?UTF-8?B?{}?=", base64::encode(value))
+    }
+}
+
+fn encode_body(data: &[u8]) -> String {
+    base64::encode(data)
+        .as_bytes()
+        .chunks(76)
+        .map(|x| String::from_utf8_lossy(x).to_string())
+        .collect::<Vec<String>>()
+        .join("\r\n")
+}
+
+fn boundary() -> String {
+    format!("olmmcc-{}", generate_verification_code())
+}
+
+fn text_part(content_type: &str, text: &str) -> String {
+    format!(
+        "Content-Type: {}; charset=utf-8\r\nContent-Transfer-Encoding: base64\r\n\r\n{}",
+        content_type,
+        encode_body(text.as_bytes())
+    )
+}
+
+fn file_part(attachment: &Attachment, inline: bool) -> String {
+    let name = encode_header(&attachment.name);
+    let mut part = format!(
+        "Content-Type: {}; name=\"{}\"\r\nContent-Transfer-Encoding: base64\r\n",
+        attachment.content_type, name
+    );
+    if inline {
+        part.push_str(&format!(
+            "Content-ID: <{}>\r\nContent-Disposition: inline; filename=\"{}\"\r\n",
+            attachment.name, name
+        ));
+    } else {
+        part.push_str(&format!(
+            "Content-Disposition: attachment; filename=\"{}\"\r\n",
+            name
+        ));
+    }
+    part.push_str("\r\n");
+    part.push_str(&encode_body(&attachment.data));
+    part
+}
+
+fn multipart(subtype: &str, parts: Vec<String>) -> String {
+    let boundary = boundary();
+    let mut multipart = format!(
+        "Content-Type: multipart/{}; boundary=\"{}\"\r\n\r\n",
+        subtype, boundary
+    );
+    for part in parts {
+        multipart.push_str(&format!("--{}\r\n{}\r\n", boundary, part));
+    }
+    multipart.push_str(&format!("--{}--", boundary));
+    multipart
+}
+
+pub fn html_to_text(html: &str) -> String {
+    let mut text = String::new();
+    let mut tag = String::new();
+    let mut in_tag = false;
+    let mut hidden = false;
+    for c in html.chars() {
+        match c {
+            '<' => {
+                in_tag = true;
+                tag.clear();
+            }
+            '>' if in_tag => {
+                in_tag = false;
+                let closing = tag.starts_with('/');
+                let name = tag
+                    .trim_start_matches('/')
+                    .split_whitespace()
+                    .next()
+                    .unwrap_or_default()
+                    .to_lowercase();
+                if name == "style" || name == "script" {
+                    hidden = !closing;
+                }
+                if ["br", "p", "div", "li", "tr", "h1", "h2", "h3", "h4"].contains(&name.as_str()) {
+                    text.push_str("\r\n");
+                }
+            }
+            _ if in_tag => tag.push(c),
+            _ if hidden => {}
+            _ => text.push(c),
+        }
+    }
+    text.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+        .trim()
+        .to_string()
+}
+
+pub fn bcc_header(email: &Email) -> String {
+    if email.bcc.is_empty() {
+        String::new()
+    } else {
+        format!("Bcc: {}\r\n", single_line(&email.bcc.join(", ")))
+    }
+}
+
+pub fn build_message(email: &Email, from: &str) -> String {
+    let mut body = match &email.html {
+        Some(html) => multipart(
+            "alternative",
+            vec![
+                text_part("text/plain", &email.body),
+                text_part("text/html", html),
+            ],
+        ),
+        None => text_part("text/plain", &email.body),
+    };
+    if !email.inline_images.is_empty() {
+        let mut parts = vec![body];
+        parts.extend(email.inline_images.iter().map(|x| file_part(x, true)));
+        body = multipart("related", parts);
+    }
+    if !email.attachments.is_empty() {
+        let mut parts = vec![body];
+        parts.extend(email.attachments.iter().map(|x| file_part(x, false)));
+        body = multipart("mixed", parts);
+    }
+    let mut message = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMessage-ID: <{}@olmmcc.tk>\r\nMIME-Version: 1.0\r\n",
+        single_line(from),
+        single_line(&email.to.join(", ")),
+        encode_header(&email.subject),
+        Utc::now().to_rfc2822(),
+        generate_verification_code()
+    );
+    for (name, value) in &email.headers {
+        message.push_str(&format!(
+            "{}: {}\r\n",
+            single_line(name),
+            single_line(value)
+        ));
+    }
+    message.push_str(&body);
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attachment(name: &str) -> Attachment {
+        Attachment {
+            name: name.to_string(),
+            content_type: content_type(name).to_string(),
+            data: vec![0; 100],
+        }
+    }
+
+    #[test]
+    fn encode_header_encodes_non_ascii_values() {
+        assert_eq!(encode_header("Concert"), "Concert");
+        assert_eq!(
+            encode_header("Konzert für alle"),
+            format!("=?UTF-8?B?{}?=", base64::encode("Konzert für alle"))
+        );
+    }
+
+    #[test]
+    fn headers_cannot_span_lines() {
+        let mut email = Email::new(
+            vec!["member@example.com".to_string()],
+            "Hi\r\nBcc: victim@example.com",
+            "Body",
+        );
+        email.headers.push((
+            "X-Test".to_string(),
+            "a\r\nBcc: victim@example.com".to_string(),
+        ));
+        let message = build_message(&email, "olmmcc@example.com");
+        let headers = message.split("\r\n\r\n").next().unwrap();
+        assert!(!headers.lines().any(|x| x.starts_with("Bcc:")));
+        assert!(headers.contains("Subject: Hi  Bcc: victim@example.com\r\n"));
+        assert!(headers.contains("X-Test: a  Bcc: victim@example.com\r\n"));
+    }
+
+    #[test]
+    fn encode_body_wraps_lines() {
+        let body = encode_body(&[255; 200]);
+        assert!(body.split("\r\n").all(|x| x.len() <= 76));
+        assert_eq!(
+            base64::decode(body.replace("\r\n", "")).unwrap(),
+            vec![255; 200]
+        );
+    }
+
+    #[test]
+    fn build_message_nests_parts() {
+        let mut email = Email::new(vec!["member@example.com".to_string()], "Hi", "Body");
+        email.html = Some("<p>Body</p>".to_string());
+        email.inline_images.push(attachment("logo.png"));
+        email.attachments.push(attachment("notes.pdf"));
+        let message = build_message(&email, "olmmcc@example.com");
+        let mixed = message.find("multipart/mixed").unwrap();
+        let related = message.find("multipart/related").unwrap();
+        let alternative = message.find("multipart/alternative").unwrap();
+        let plain = message.find("text/plain").unwrap();
+        let html = message.find("text/html").unwrap();
+        let inline = message.find("Content-Disposition: inline").unwrap();
+        let attached = message.find("Content-Disposition: attachment").unwrap();
+        assert!(mixed < related && related < alternative);
+        assert!(alternative < plain && plain < html && html < inline && inline < attached);
+        assert!(message.contains("Content-ID: <logo.png>"));
+        assert!(message.contains("MIME-Version: 1.0\r\n"));
+        assert_eq!(message.matches("--olmmcc-").count(), 9);
+    }
+
+    #[test]
+    fn build_message_sends_plain_text_alone() {
+        let email = Email::new(vec!["member@example.com".to_string()], "Hi", "Body");
+        let message = build_message(&email, "olmmcc@example.com");
+        assert!(!message.contains("multipart"));
+        assert!(message.ends_with(&base64::encode("Body")));
+    }
+
+    #[test]
+    fn html_to_text_keeps_visible_text() {
+        assert_eq!(
+            html_to_text(
+                "<html><head><style>p { color: red; }</style></head><body><p>Tom &amp; Jerry</p>\
+                 <script>alert(\"hi\")</script><p>See&nbsp;you</p></body></html>"
+            ),
+            "Tom & Jerry\r\n\r\nSee you"
+        );
+    }
+}
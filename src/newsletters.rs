@@ -0,0 +1,301 @@
+use chrono::{Local, NaiveDateTime, TimeZone};
+use mysql::*;
+use serde::Serialize;
+use tokio::time::{delay_for, Duration};
+
+use std::collections::HashMap;
+
+use crate::accounts::is_audience;
+use crate::config::*;
+use crate::{deliver_email, insert_row_with_id, unix_time};
+
+const MAX_SEND_ATTEMPTS: i32 = 3;
+
+const MESSAGE_FIELDS: &[&str] = &[
+    "subject",
+    "body",
+    "html",
+    "inline_images",
+    "attachments",
+    "recipients",
+    "recipient",
+    "delivery",
+    "batch_size",
+    "track_opens",
+    "sender",
+];
+
+#[derive(Serialize)]
+pub struct Newsletter {
+    id: i32,
+    subject: String,
+    recipients: String,
+    message: HashMap<String, String>,
+    status: String,
+    send_at: i64,
+    created: i64,
+    updated: i64,
+    sent: i64,
+    count: i32,
+    last_error: String,
+    attempts: i32,
+}
+
+impl Newsletter {
+    pub fn message(&self) -> HashMap<&str, &str> {
+        self.message
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect()
+    }
+}
+
+async fn get_newsletters_like(key: &str, value: &str) -> Vec<Newsletter> {
+    get_like("newsletters", key, value)
+        .await
+        .iter()
+        .map(|row| Newsletter {
+            id: from_value(row[0].clone()),
+            subject: from_value(row[1].clone()),
+            recipients: from_value(row[2].clone()),
+            message: serde_json::from_str(&from_value::<String>(row[3].clone()))
+                .unwrap_or_default(),
+            status: from_value(row[4].clone()),
+            send_at: from_value(row[5].clone()),
+            created: from_value(row[6].clone()),
+            updated: from_value(row[7].clone()),
+            sent: from_value(row[8].clone()),
+            count: from_value(row[9].clone()),
+            last_error: from_value(row[10].clone()),
+            attempts: from_value(row[12].clone()),
+        })
+        .collect()
+}
+
+pub async fn get_newsletter(id: &str) -> Option<Newsletter> {
+    get_newsletters_like("id", id)
+        .await
+        .into_iter()
+        .find(|newsletter| newsletter.id.to_string() == id)
+}
+
+pub async fn get_newsletters(status: Option<&str>) -> Vec<Newsletter> {
+    get_newsletters_like("status", status.unwrap_or("%"))
+        .await
+        .into_iter()
+        .filter(|newsletter| status.is_none_or(|x| newsletter.status == x))
+        .collect()
+}
+
+fn check_message(message: &HashMap<String, String>) -> Result<(), String> {
+    let filled = |field: &str| message.get(field).is_some_and(|x| !x.is_empty());
+    if !filled("subject") {
+        return Err("Please enter a subject for this newsletter.".to_string());
+    }
+    if !message.get("recipients").is_some_and(|x| is_audience(x)) && !filled("recipient") {
+        return Err("Please choose who to send this newsletter to.".to_string());
+    }
+    Ok(())
+}
+
+fn is_editable(status: &str) -> bool {
+    status == "draft" || status == "scheduled"
+}
+
+pub async fn save_newsletter(id: Option<&str>, body: &HashMap<&str, &str>) -> Result<i32, String> {
+    let now = unix_time().to_string();
+    let mut message = match id {
+        Some(id) => {
+            let newsletter = get_newsletter(id)
+                .await
+                .ok_or_else(|| "That newsletter does not exist.".to_string())?;
+            if !is_editable(&newsletter.status) {
+                return Err("Only drafts and scheduled newsletters can be edited.".to_string());
+            }
+            newsletter.message
+        }
+        None => HashMap::new(),
+    };
+    for field in MESSAGE_FIELDS {
+        if let Some(value) = body.get(field) {
+            message.insert(field.to_string(), value.to_string());
+        }
+    }
+    let subject = message.get("subject").cloned().unwrap_or_default();
+    if subject.is_empty() {
+        return Err("Please enter a subject for this newsletter.".to_string());
+    }
+    let recipients = message.get("recipients").cloned().unwrap_or_default();
+    let message = serde_json::to_string(&message).unwrap();
+    match id {
+        Some(id) => {
+            change_row_where("newsletters", "id", id, "subject", &subject).await;
+            change_row_where("newsletters", "id", id, "recipients", &recipients).await;
+            change_row_where("newsletters", "id", id, "message", &message).await;
+            change_row_where("newsletters", "id", id, "updated", &now).await;
+            Ok(id.parse().unwrap())
+        }
+        None => {
+            insert_row_with_id(
+                "newsletters",
+                vec![
+                    "subject",
+                    "recipients",
+                    "message",
+                    "status",
+                    "send_at",
+                    "created",
+                    "updated",
+                    "sent",
+                    "count",
+                    "last_error",
+                ],
+                vec![
+                    &subject,
+                    &recipients,
+                    &message,
+                    "draft",
+                    "0",
+                    &now,
+                    &now,
+                    "0",
+                    "0",
+                    "",
+                ],
+            )
+            .await
+        }
+    }
+}
+
+pub fn parse_send_at(send_at: &str) -> Option<i64> {
+    if let Ok(t) = send_at.parse() {
+        return Some(t);
+    }
+    ["%Y-%m-%dT%H:%M", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .filter_map(|format| NaiveDateTime::parse_from_str(send_at, format).ok())
+        .next()
+        .and_then(|x| Local.from_local_datetime(&x).earliest())
+        .map(|x| x.timestamp())
+}
+
+pub async fn schedule_newsletter(id: &str, send_at: i64) -> Result<(), String> {
+    let newsletter = get_newsletter(id)
+        .await
+        .ok_or_else(|| "That newsletter does not exist.".to_string())?;
+    if !is_editable(&newsletter.status) {
+        return Err("This newsletter has already been sent.".to_string());
+    }
+    check_message(&newsletter.message)?;
+    if send_at < unix_time() - 60 {
+        return Err("Newsletters cannot be scheduled in the past.".to_string());
+    }
+    change_row_where("newsletters", "id", id, "send_at", &send_at.to_string()).await;
+    change_row_where("newsletters", "id", id, "status", "scheduled").await;
+    Ok(())
+}
+
+pub async fn cancel_newsletter(id: &str) -> bool {
+    match get_newsletter(id).await {
+        Some(newsletter) if newsletter.status == "scheduled" => {
+            change_row_where("newsletters", "id", id, "status", "draft").await;
+            change_row_where("newsletters", "id", id, "send_at", "0").await;
+            true
+        }
+        _ => false,
+    }
+}
+
+async fn send_newsletter(newsletter: Newsletter) {
+    let id = newsletter.id.to_string();
+    change_row_where("newsletters", "id", &id, "status", "sending").await;
+    change_row_where(
+        "newsletters",
+        "id",
+        &id,
+        "updated",
+        &unix_time().to_string(),
+    )
+    .await;
+    change_row_where(
+        "newsletters",
+        "id",
+        &id,
+        "attempts",
+        &(newsletter.attempts + 1).to_string(),
+    )
+    .await;
+    let result = match check_message(&newsletter.message) {
+        Ok(()) => deliver_email(&newsletter.message()).await,
+        Err(e) => serde_json::json!({"success": false, "message": e}),
+    };
+    if result["success"] == true {
+        change_row_where("newsletters", "id", &id, "status", "sent").await;
+        change_row_where("newsletters", "id", &id, "sent", &unix_time().to_string()).await;
+        change_row_where(
+            "newsletters",
+            "id",
+            &id,
+            "count",
+            &result["count"].to_string(),
+        )
+        .await;
+    } else {
+        let error = result["message"]
+            .as_str()
+            .unwrap_or("The newsletter could not be sent.");
+        change_row_where("newsletters", "id", &id, "status", "failed").await;
+        change_row_where("newsletters", "id", &id, "last_error", error).await;
+    }
+}
+
+async fn requeue_stale_sends() {
+    let stale = unix_time() - newsletter_send_timeout();
+    for newsletter in get_newsletters(Some("sending")).await {
+        if newsletter.updated > stale {
+            continue;
+        }
+        let id = newsletter.id.to_string();
+        if newsletter.attempts >= MAX_SEND_ATTEMPTS {
+            change_row_where("newsletters", "id", &id, "status", "failed").await;
+            change_row_where(
+                "newsletters",
+                "id",
+                &id,
+                "last_error",
+                "Sending was interrupted too many times.",
+            )
+            .await;
+        } else {
+            change_row_where("newsletters", "id", &id, "status", "scheduled").await;
+            change_row_where(
+                "newsletters",
+                "id",
+                &id,
+                "last_error",
+                "The last send attempt was interrupted, so it will be retried.",
+            )
+            .await;
+        }
+    }
+}
+
+async fn send_due_newsletters() {
+    requeue_stale_sends().await;
+    let now = unix_time();
+    for newsletter in get_newsletters(Some("scheduled")).await {
+        if newsletter.send_at <= now {
+            send_newsletter(newsletter).await;
+        }
+    }
+}
+
+pub async fn run_newsletter_scheduler() {
+    loop {
+        if let Err(e) = tokio::spawn(send_due_newsletters()).await {
+            eprintln!("Newsletter pass failed: {}", e);
+        }
+        delay_for(Duration::from_secs(newsletter_poll_interval())).await;
+    }
+}
@@ -0,0 +1,192 @@
+use mysql::*;
+use serde::Serialize;
+use tokio::time::{delay_for, Duration};
+
+use std::collections::HashMap;
+
+use crate::config::*;
+use crate::mailer::*;
+use crate::{insert_row_with_id, unix_time};
+
+#[derive(Serialize)]
+pub struct OutboxMessage {
+    id: i32,
+    recipients: String,
+    subject: String,
+    #[serde(skip)]
+    email: String,
+    status: String,
+    attempts: i32,
+    next_attempt: i64,
+    last_error: String,
+    created: i64,
+    sent: i64,
+    campaign_id: i32,
+}
+
+pub struct Delivery {
+    pub status: String,
+    pub last_error: String,
+    pub sent: i64,
+}
+
+async fn get_messages_like(key: &str, value: &str) -> Vec<OutboxMessage> {
+    get_like("outbox", key, value)
+        .await
+        .iter()
+        .map(|row| OutboxMessage {
+            id: from_value(row[0].clone()),
+            recipients: from_value(row[1].clone()),
+            subject: from_value(row[2].clone()),
+            email: from_value(row[3].clone()),
+            status: from_value(row[4].clone()),
+            attempts: from_value(row[5].clone()),
+            next_attempt: from_value(row[6].clone()),
+            last_error: from_value(row[7].clone()),
+            created: from_value(row[8].clone()),
+            sent: from_value(row[9].clone()),
+            campaign_id: from_value(row[10].clone()),
+        })
+        .collect()
+}
+
+pub async fn queue_mail(email: Email) -> i32 {
+    queue_mail_at(email, unix_time()).await
+}
+
+pub async fn queue_mail_at(email: Email, send_at: i64) -> i32 {
+    let now = unix_time().to_string();
+    let recipients: Vec<String> = email.to.iter().chain(email.bcc.iter()).cloned().collect();
+    insert_row_with_id(
+        "outbox",
+        vec![
+            "recipients",
+            "subject",
+            "email",
+            "status",
+            "attempts",
+            "next_attempt",
+            "last_error",
+            "created",
+            "sent",
+        ],
+        vec![
+            &recipients.join(", "),
+            &email.subject,
+            &serde_json::to_string(&email).unwrap(),
+            "pending",
+            "0",
+            &send_at.to_string(),
+            "",
+            &now,
+            "0",
+        ],
+    )
+    .await
+    .unwrap()
+}
+
+pub async fn queue_campaign_mail(email: Email, send_at: i64, campaign_id: i32) -> i32 {
+    let id = queue_mail_at(email, send_at).await;
+    change_row_where(
+        "outbox",
+        "id",
+        &id.to_string(),
+        "campaign_id",
+        &campaign_id.to_string(),
+    )
+    .await;
+    id
+}
+
+pub async fn get_campaign_deliveries(campaign_id: &str) -> HashMap<i32, Delivery> {
+    get_messages_like("campaign_id", campaign_id)
+        .await
+        .into_iter()
+        .filter(|message| campaign_id == "%" || message.campaign_id.to_string() == campaign_id)
+        .map(|message| {
+            (
+                message.id,
+                Delivery {
+                    status: message.status,
+                    last_error: message.last_error,
+                    sent: message.sent,
+                },
+            )
+        })
+        .collect()
+}
+
+pub async fn get_outbox_messages(status: &str) -> Vec<OutboxMessage> {
+    get_messages_like("status", status)
+        .await
+        .into_iter()
+        .filter(|message| message.status == status)
+        .collect()
+}
+
+pub async fn resend_message(id: &str) -> bool {
+    if row_exists("outbox", "id", id).await {
+        change_row_where("outbox", "id", id, "status", "pending").await;
+        change_row_where("outbox", "id", id, "attempts", "0").await;
+        change_row_where("outbox", "id", id, "next_attempt", &unix_time().to_string()).await;
+        true
+    } else {
+        false
+    }
+}
+
+fn retry_delay(attempts: i32) -> i64 {
+    (60 * 2_i64.pow(attempts.min(16) as u32)).min(6 * 60 * 60)
+}
+
+async fn deliver(message: OutboxMessage) {
+    let id = message.id.to_string();
+    let attempts = message.attempts + 1;
+    let result = match serde_json::from_str::<Email>(&message.email) {
+        Ok(email) => send_mail(email).await,
+        Err(e) => Err(e.to_string()),
+    };
+    change_row_where("outbox", "id", &id, "attempts", &attempts.to_string()).await;
+    match result {
+        Ok(()) => {
+            change_row_where("outbox", "id", &id, "status", "sent").await;
+            change_row_where("outbox", "id", &id, "sent", &unix_time().to_string()).await;
+            change_row_where("outbox", "id", &id, "last_error", "").await;
+        }
+        Err(e) => {
+            change_row_where("outbox", "id", &id, "last_error", &e).await;
+            if attempts >= outbox_max_attempts() {
+                change_row_where("outbox", "id", &id, "status", "failed").await;
+            } else {
+                let next_attempt = unix_time() + retry_delay(message.attempts);
+                change_row_where(
+                    "outbox",
+                    "id",
+                    &id,
+                    "next_attempt",
+                    &next_attempt.to_string(),
+                )
+                .await;
+            }
+        }
+    }
+}
+
+async fn deliver_due_messages() {
+    let now = unix_time();
+    for message in get_outbox_messages("pending").await {
+        if message.next_attempt <= now {
+            deliver(message).await;
+        }
+    }
+}
+
+pub async fn run_outbox_worker() {
+    loop {
+        if let Err(e) = tokio::spawn(deliver_due_messages()).await {
+            eprintln!("Outbox delivery pass failed: {}", e);
+        }
+        delay_for(Duration::from_secs(outbox_poll_interval())).await;
+    }
+}
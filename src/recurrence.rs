@@ -0,0 +1,534 @@
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+
+use std::convert::TryFrom;
+
+const MAX_PERIODS: i64 = 5000;
+const MAX_INTERVAL: i64 = 1000;
+const MAX_COUNT: usize = 5000;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+pub struct Recurrence {
+    frequency: Frequency,
+    interval: i64,
+    until: Option<NaiveDate>,
+    count: Option<usize>,
+    by_day: Vec<(Option<i32>, Weekday)>,
+}
+
+fn parse_weekday(day: &str) -> Option<Weekday> {
+    match day {
+        "MO" => Some(Weekday::Mon),
+        "TU" => Some(Weekday::Tue),
+        "WE" => Some(Weekday::Wed),
+        "TH" => Some(Weekday::Thu),
+        "FR" => Some(Weekday::Fri),
+        "SA" => Some(Weekday::Sat),
+        "SU" => Some(Weekday::Sun),
+        _ => None,
+    }
+}
+
+fn weekday_code(day: Weekday) -> &'static str {
+    match day {
+        Weekday::Mon => "MO",
+        Weekday::Tue => "TU",
+        Weekday::Wed => "WE",
+        Weekday::Thu => "TH",
+        Weekday::Fri => "FR",
+        Weekday::Sat => "SA",
+        Weekday::Sun => "SU",
+    }
+}
+
+pub fn parse_date(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(date.get(..8).unwrap_or(date), "%Y%m%d"))
+        .ok()
+}
+
+fn weekdays_in_month(year: i32, month: u32, weekday: Weekday) -> Vec<NaiveDate> {
+    (1..=31)
+        .filter_map(|day| NaiveDate::from_ymd_opt(year, month, day))
+        .filter(|date| date.weekday() == weekday)
+        .collect()
+}
+
+fn week_start(date: NaiveDate) -> NaiveDate {
+    date - Duration::days(date.weekday().num_days_from_monday() as i64)
+}
+
+fn month_index(date: NaiveDate) -> i64 {
+    date.year() as i64 * 12 + date.month0() as i64
+}
+
+impl Recurrence {
+    pub fn parse(rule: &str) -> Result<Recurrence, String> {
+        let mut recurrence = Recurrence {
+            frequency: Frequency::Weekly,
+            interval: 1,
+            until: None,
+            count: None,
+            by_day: Vec::new(),
+        };
+        let mut has_frequency = false;
+        let rule = rule.trim().trim_start_matches("RRULE:").to_uppercase();
+        for part in rule.split(';').filter(|x| !x.is_empty()) {
+            let mut pair = part.splitn(2, '=');
+            let (name, value) = match (pair.next(), pair.next()) {
+                (Some(name), Some(value)) => (name, value),
+                _ => return Err(format!("{} is not a valid recurrence rule part.", part)),
+            };
+            match name {
+                "FREQ" => {
+                    recurrence.frequency = match value {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        _ => return Err(format!("{} is not a supported frequency.", value)),
+                    };
+                    has_frequency = true;
+                }
+                "INTERVAL" => {
+                    recurrence.interval = value
+                        .parse()
+                        .ok()
+                        .filter(|x| *x > 0 && *x <= MAX_INTERVAL)
+                        .ok_or_else(|| format!("{} is not a valid interval.", value))?;
+                }
+                "UNTIL" => {
+                    recurrence.until = Some(
+                        parse_date(value)
+                            .ok_or_else(|| format!("{} is not a valid end date.", value))?,
+                    );
+                }
+                "COUNT" => {
+                    recurrence.count = Some(
+                        value
+                            .parse()
+                            .ok()
+                            .filter(|x| *x > 0 && *x <= MAX_COUNT)
+                            .ok_or_else(|| format!("{} is not a valid count.", value))?,
+                    );
+                }
+                "BYDAY" => {
+                    for day in value.split(',') {
+                        let split = day.char_indices().rev().nth(1).map_or(0, |(i, _)| i);
+                        let (ordinal, code) = day.split_at(split);
+                        let weekday = parse_weekday(code)
+                            .ok_or_else(|| format!("{} is not a valid day.", day))?;
+                        let ordinal = if ordinal.is_empty() {
+                            None
+                        } else {
+                            Some(
+                                ordinal
+                                    .trim_start_matches('+')
+                                    .parse::<i32>()
+                                    .ok()
+                                    .filter(|x| *x != 0 && x.unsigned_abs() <= 53)
+                                    .ok_or_else(|| format!("{} is not a valid day.", day))?,
+                            )
+                        };
+                        recurrence.by_day.push((ordinal, weekday));
+                    }
+                }
+                _ => return Err(format!("{} is not a supported recurrence rule part.", name)),
+            }
+        }
+        if !has_frequency {
+            return Err("Recurrence rules must include FREQ.".to_string());
+        }
+        let max_ordinal = match recurrence.frequency {
+            Frequency::Monthly => 5,
+            Frequency::Yearly => 53,
+            _ => 0,
+        };
+        if recurrence
+            .by_day
+            .iter()
+            .any(|(ordinal, _)| ordinal.is_some_and(|n| n.unsigned_abs() > max_ordinal))
+        {
+            return Err("That day number does not fit the recurrence frequency.".to_string());
+        }
+        Ok(recurrence)
+    }
+
+    pub fn to_rrule(&self, format_until: impl Fn(NaiveDate) -> String) -> String {
+        let mut parts = vec![format!(
+            "FREQ={}",
+            match self.frequency {
+                Frequency::Daily => "DAILY",
+                Frequency::Weekly => "WEEKLY",
+                Frequency::Monthly => "MONTHLY",
+                Frequency::Yearly => "YEARLY",
+            }
+        )];
+        if self.interval != 1 {
+            parts.push(format!("INTERVAL={}", self.interval));
+        }
+        if let Some(count) = self.count {
+            parts.push(format!("COUNT={}", count));
+        }
+        if let Some(until) = self.until {
+            parts.push(format!("UNTIL={}", format_until(until)));
+        }
+        if !self.by_day.is_empty() {
+            let days: Vec<String> = self
+                .by_day
+                .iter()
+                .map(|(ordinal, day)| match ordinal {
+                    Some(n) => format!("{}{}", n, weekday_code(*day)),
+                    None => weekday_code(*day).to_string(),
+                })
+                .collect();
+            parts.push(format!("BYDAY={}", days.join(",")));
+        }
+        parts.join(";")
+    }
+
+    fn period_dates(&self, start: NaiveDate, period: i64) -> Option<Vec<NaiveDate>> {
+        let step = period.checked_mul(self.interval)?;
+        let mut dates = match self.frequency {
+            Frequency::Daily => vec![start.checked_add_signed(Duration::days(step))?],
+            Frequency::Weekly => {
+                let week = week_start(start).checked_add_signed(Duration::weeks(step))?;
+                let weekdays = if self.by_day.is_empty() {
+                    vec![start.weekday()]
+                } else {
+                    self.by_day.iter().map(|(_, day)| *day).collect()
+                };
+                weekdays
+                    .iter()
+                    .filter_map(|day| {
+                        week.checked_add_signed(Duration::days(day.num_days_from_monday() as i64))
+                    })
+                    .collect()
+            }
+            Frequency::Monthly => {
+                let months = month_index(start).checked_add(step)?;
+                let year = i32::try_from(months.div_euclid(12)).ok()?;
+                let month = months.rem_euclid(12) as u32 + 1;
+                NaiveDate::from_ymd_opt(year, month, 1)?;
+                if self.by_day.is_empty() {
+                    NaiveDate::from_ymd_opt(year, month, start.day())
+                        .into_iter()
+                        .collect()
+                } else {
+                    let mut dates = Vec::new();
+                    for (ordinal, weekday) in &self.by_day {
+                        let days = weekdays_in_month(year, month, *weekday);
+                        match ordinal {
+                            None => dates.extend(days),
+                            Some(n) if *n > 0 => dates.extend(days.get(*n as usize - 1)),
+                            Some(n) => dates.extend(
+                                days.len()
+                                    .checked_sub(n.unsigned_abs() as usize)
+                                    .map(|i| days[i]),
+                            ),
+                        }
+                    }
+                    dates
+                }
+            }
+            Frequency::Yearly => {
+                let year = i32::try_from(start.year() as i64 + step).ok()?;
+                NaiveDate::from_ymd_opt(year, 1, 1)?;
+                NaiveDate::from_ymd_opt(year, start.month(), start.day())
+                    .into_iter()
+                    .collect()
+            }
+        };
+        dates.sort();
+        dates.dedup();
+        Some(dates)
+    }
+
+    fn first_period(&self, start: NaiveDate, from: NaiveDate) -> i64 {
+        if self.count.is_some() || from <= start {
+            return 0;
+        }
+        let elapsed = match self.frequency {
+            Frequency::Daily => (from - start).num_days(),
+            Frequency::Weekly => (week_start(from) - week_start(start)).num_weeks(),
+            Frequency::Monthly => month_index(from) - month_index(start),
+            Frequency::Yearly => (from.year() - start.year()) as i64,
+        };
+        elapsed / self.interval
+    }
+
+    pub fn dates(&self, start: NaiveDate, from: NaiveDate, to: NaiveDate) -> Vec<NaiveDate> {
+        let end = match self.until {
+            Some(until) => until.min(to),
+            None => to,
+        };
+        let mut dates = Vec::new();
+        let mut occurrences = 0;
+        let first = self.first_period(start, from);
+        let last = match self.count {
+            Some(count) => first + count as i64 * 12 + 12,
+            None => first + MAX_PERIODS,
+        };
+        for period in first..last {
+            let period_dates = match self.period_dates(start, period) {
+                Some(t) => t,
+                None => break,
+            };
+            if period_dates.first().is_some_and(|x| *x > end) {
+                break;
+            }
+            for date in period_dates {
+                if date < start || date > end {
+                    continue;
+                }
+                occurrences += 1;
+                if self.count.is_some_and(|count| occurrences > count) {
+                    return dates;
+                }
+                if date >= from {
+                    dates.push(date);
+                }
+            }
+        }
+        dates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd(year, month, day)
+    }
+
+    fn dates(rule: &str, start: NaiveDate, from: NaiveDate, to: NaiveDate) -> Vec<NaiveDate> {
+        Recurrence::parse(rule).unwrap().dates(start, from, to)
+    }
+
+    #[test]
+    fn parse_rejects_invalid_rules() {
+        assert!(Recurrence::parse("INTERVAL=2").is_err());
+        assert!(Recurrence::parse("FREQ=HOURLY").is_err());
+        assert!(Recurrence::parse("FREQ=DAILY;INTERVAL=0").is_err());
+        assert!(Recurrence::parse("FREQ=DAILY;BYMONTH=1").is_err());
+        assert!(Recurrence::parse("FREQ=WEEKLY;BYDAY=XX").is_err());
+        assert!(Recurrence::parse("FREQ=WEEKLY;BYDAY=").is_err());
+    }
+
+    #[test]
+    fn parse_rejects_out_of_range_values() {
+        assert!(Recurrence::parse("FREQ=DAILY;INTERVAL=100000000").is_err());
+        assert!(Recurrence::parse("FREQ=DAILY;COUNT=0").is_err());
+        assert!(Recurrence::parse("FREQ=MONTHLY;BYDAY=-2147483648MO").is_err());
+        assert!(Recurrence::parse("FREQ=MONTHLY;BYDAY=6MO").is_err());
+        assert!(Recurrence::parse("FREQ=WEEKLY;BYDAY=1MO").is_err());
+        assert!(Recurrence::parse("FREQ=YEARLY;BYDAY=53MO").is_ok());
+    }
+
+    #[test]
+    fn large_intervals_do_not_overflow() {
+        let start = date(2024, 1, 1);
+        for rule in &[
+            "FREQ=DAILY;INTERVAL=1000",
+            "FREQ=WEEKLY;INTERVAL=1000;BYDAY=MO,SU",
+            "FREQ=MONTHLY;INTERVAL=1000;BYDAY=1MO,-1MO",
+            "FREQ=YEARLY;INTERVAL=1000",
+        ] {
+            assert_eq!(
+                dates(rule, start, start, NaiveDate::from_ymd(262000, 1, 1)).first(),
+                Some(&start)
+            );
+        }
+    }
+
+    #[test]
+    fn dates_long_after_the_start() {
+        assert_eq!(
+            dates(
+                "FREQ=DAILY",
+                date(2000, 1, 1),
+                date(2024, 3, 1),
+                date(2024, 3, 3)
+            ),
+            vec![date(2024, 3, 1), date(2024, 3, 2), date(2024, 3, 3)]
+        );
+        assert_eq!(
+            dates(
+                "FREQ=WEEKLY;INTERVAL=2;BYDAY=TU",
+                date(2000, 1, 4),
+                date(2024, 3, 1),
+                date(2024, 3, 31)
+            ),
+            vec![date(2024, 3, 12), date(2024, 3, 26)]
+        );
+        assert_eq!(
+            dates(
+                "FREQ=MONTHLY;INTERVAL=5;BYDAY=1FR",
+                date(2000, 1, 7),
+                date(2024, 1, 1),
+                date(2024, 12, 31)
+            ),
+            vec![date(2024, 3, 1), date(2024, 8, 2)]
+        );
+    }
+
+    #[test]
+    fn parse_rejects_non_ascii_days() {
+        assert!(Recurrence::parse("FREQ=MONTHLY;BYDAY=1Ü").is_err());
+        assert!(Recurrence::parse("FREQ=MONTHLY;BYDAY=ÜÜÜ").is_err());
+        assert!(Recurrence::parse("FREQ=MONTHLY;BYDAY=Ü").is_err());
+        assert!(Recurrence::parse("FREQ=MONTHLY;BYDAY=ÜA").is_err());
+        assert!(Recurrence::parse("FREQ=MONTHLY;BYDAY=1ÜO").is_err());
+    }
+
+    #[test]
+    fn parse_round_trips_through_rrule() {
+        let rule = "FREQ=MONTHLY;INTERVAL=2;COUNT=4;BYDAY=-1FR,2TU";
+        let date_only = |x: NaiveDate| x.format("%Y%m%d").to_string();
+        assert_eq!(Recurrence::parse(rule).unwrap().to_rrule(date_only), rule);
+        let recurrence = Recurrence::parse("RRULE:freq=monthly;until=20240301;byday=+1mo").unwrap();
+        assert_eq!(
+            recurrence.to_rrule(|x| x.format("%Y%m%dT235959Z").to_string()),
+            "FREQ=MONTHLY;UNTIL=20240301T235959Z;BYDAY=1MO"
+        );
+    }
+
+    #[test]
+    fn weekly_by_day_skips_days_before_start() {
+        assert_eq!(
+            dates(
+                "FREQ=WEEKLY;BYDAY=MO,WE",
+                date(2024, 1, 3),
+                date(2024, 1, 1),
+                date(2024, 1, 14)
+            ),
+            vec![date(2024, 1, 3), date(2024, 1, 8), date(2024, 1, 10)]
+        );
+    }
+
+    #[test]
+    fn weekly_interval() {
+        assert_eq!(
+            dates(
+                "FREQ=WEEKLY;INTERVAL=2",
+                date(2024, 1, 3),
+                date(2024, 1, 1),
+                date(2024, 2, 1)
+            ),
+            vec![date(2024, 1, 3), date(2024, 1, 17), date(2024, 1, 31)]
+        );
+    }
+
+    #[test]
+    fn monthly_ordinal_days() {
+        assert_eq!(
+            dates(
+                "FREQ=MONTHLY;BYDAY=2TU",
+                date(2024, 1, 9),
+                date(2024, 1, 1),
+                date(2024, 3, 31)
+            ),
+            vec![date(2024, 1, 9), date(2024, 2, 13), date(2024, 3, 12)]
+        );
+        assert_eq!(
+            dates(
+                "FREQ=MONTHLY;BYDAY=-1FR",
+                date(2024, 1, 26),
+                date(2024, 1, 1),
+                date(2024, 4, 30)
+            ),
+            vec![
+                date(2024, 1, 26),
+                date(2024, 2, 23),
+                date(2024, 3, 29),
+                date(2024, 4, 26)
+            ]
+        );
+    }
+
+    #[test]
+    fn monthly_fifth_weekday_skips_short_months() {
+        assert_eq!(
+            dates(
+                "FREQ=MONTHLY;BYDAY=5FR",
+                date(2024, 3, 29),
+                date(2024, 3, 1),
+                date(2024, 6, 30)
+            ),
+            vec![date(2024, 3, 29), date(2024, 5, 31)]
+        );
+    }
+
+    #[test]
+    fn monthly_on_the_31st_skips_short_months() {
+        assert_eq!(
+            dates(
+                "FREQ=MONTHLY",
+                date(2024, 1, 31),
+                date(2024, 1, 1),
+                date(2024, 5, 31)
+            ),
+            vec![date(2024, 1, 31), date(2024, 3, 31), date(2024, 5, 31)]
+        );
+    }
+
+    #[test]
+    fn yearly_on_leap_day() {
+        assert_eq!(
+            dates(
+                "FREQ=YEARLY",
+                date(2024, 2, 29),
+                date(2024, 1, 1),
+                date(2032, 12, 31)
+            ),
+            vec![date(2024, 2, 29), date(2028, 2, 29), date(2032, 2, 29)]
+        );
+    }
+
+    #[test]
+    fn count_with_by_day() {
+        assert_eq!(
+            dates(
+                "FREQ=WEEKLY;COUNT=3;BYDAY=TU,TH",
+                date(2024, 1, 2),
+                date(2024, 1, 1),
+                date(2024, 12, 31)
+            ),
+            vec![date(2024, 1, 2), date(2024, 1, 4), date(2024, 1, 9)]
+        );
+    }
+
+    #[test]
+    fn count_includes_occurrences_before_the_range() {
+        assert_eq!(
+            dates(
+                "FREQ=DAILY;COUNT=5",
+                date(2024, 1, 1),
+                date(2024, 1, 4),
+                date(2024, 1, 31)
+            ),
+            vec![date(2024, 1, 4), date(2024, 1, 5)]
+        );
+    }
+
+    #[test]
+    fn until_is_inclusive() {
+        let expected = vec![date(2024, 1, 1), date(2024, 1, 2), date(2024, 1, 3)];
+        for rule in &[
+            "FREQ=DAILY;UNTIL=20240103",
+            "FREQ=DAILY;UNTIL=20240103T235959Z",
+        ] {
+            assert_eq!(
+                dates(rule, date(2024, 1, 1), date(2024, 1, 1), date(2024, 12, 31)),
+                expected
+            );
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use chrono::{Duration, Local};
+use mysql::*;
+use tokio::time::delay_for;
+
+use crate::accounts::*;
+use crate::calendar::*;
+use crate::config::*;
+use crate::outbox::*;
+use crate::templates::*;
+use crate::unix_time;
+use crate::unsubscribe::*;
+
+async fn reminder_sent(event: &CalendarEvent) -> bool {
+    get_like("calendar_reminders", "event_id", &event.id.to_string())
+        .await
+        .iter()
+        .any(|row| {
+            from_value::<i64>(row[1].clone()) == event.id
+                && from_value::<String>(row[2].clone()) == event.date
+        })
+}
+
+async fn send_reminder(event: &CalendarEvent) -> Result<(), String> {
+    let date = event.naive_date().format("%A, %B %-d, %Y").to_string();
+    let variables = [
+        ("title", event.title.as_str()),
+        ("date", date.as_str()),
+        ("start_time", event.start_time.as_str()),
+        ("end_time", event.end_time.as_str()),
+        ("notes", event.notes.as_str()),
+    ];
+    for account in get_audience("reminder_subscribers").await {
+        let mut email = render_template("reminder", &variables, vec![account.email.clone()]).await;
+        add_unsubscribe(&mut email, &account.id.to_string())?;
+        queue_mail(email).await;
+    }
+    insert_row(
+        "calendar_reminders",
+        vec!["event_id", "date", "sent"],
+        vec![&event.id.to_string(), &event.date, &unix_time().to_string()],
+    )
+    .await
+}
+
+async fn send_due_reminders() {
+    let now = unix_time();
+    let today = Local::today().naive_local();
+    let until = today + Duration::seconds(reminder_lead_time()) + Duration::days(1);
+    for event in get_events_between(today, until, None).await {
+        let start = event.start_timestamp();
+        if start > now && start - reminder_lead_time() <= now && !reminder_sent(&event).await {
+            if let Err(e) = send_reminder(&event).await {
+                eprintln!("Failed to send reminders for \"{}\": {}", event.title, e);
+                return;
+            }
+        }
+    }
+}
+
+pub async fn run_reminder_scheduler() {
+    loop {
+        if let Err(e) = tokio::spawn(send_due_reminders()).await {
+            eprintln!("Reminder pass failed: {}", e);
+        }
+        delay_for(std::time::Duration::from_secs(reminder_poll_interval())).await;
+    }
+}
@@ -0,0 +1,129 @@
+use mysql::*;
+use serde::Serialize;
+
+use std::collections::HashMap;
+
+use crate::accounts::*;
+use crate::unix_time;
+
+pub const RSVP_STATUSES: &[&str] = &["yes", "no", "maybe"];
+
+pub struct Rsvp {
+    id: i32,
+    pub event_id: i64,
+    user_id: i32,
+    pub date: String,
+    pub status: String,
+    updated: i64,
+}
+
+#[derive(Serialize)]
+pub struct Attendee {
+    user_id: i32,
+    email: String,
+    date: String,
+    status: String,
+    updated: i64,
+}
+
+#[derive(Default, Serialize)]
+pub struct AttendanceCounts {
+    yes: usize,
+    no: usize,
+    maybe: usize,
+}
+
+async fn get_rsvps_like(key: &str, value: &str) -> Vec<Rsvp> {
+    get_like("calendar_rsvps", key, value)
+        .await
+        .iter()
+        .map(|row| Rsvp {
+            id: from_value(row[0].clone()),
+            event_id: from_value(row[1].clone()),
+            user_id: from_value(row[2].clone()),
+            date: from_value(row[3].clone()),
+            status: from_value(row[4].clone()),
+            updated: from_value(row[5].clone()),
+        })
+        .collect()
+}
+
+pub async fn get_user_rsvps(user_id: &str) -> Vec<Rsvp> {
+    get_rsvps_like("user_id", user_id)
+        .await
+        .into_iter()
+        .filter(|rsvp| rsvp.user_id.to_string() == user_id)
+        .collect()
+}
+
+async fn find_rsvp(event_id: i64, date: &str, user_id: &str) -> Option<Rsvp> {
+    get_user_rsvps(user_id)
+        .await
+        .into_iter()
+        .find(|rsvp| rsvp.event_id == event_id && rsvp.date == date)
+}
+
+pub async fn set_rsvp(
+    event_id: i64,
+    date: &str,
+    user_id: &str,
+    status: &str,
+) -> Result<(), String> {
+    let now = unix_time().to_string();
+    if find_rsvp(event_id, date, user_id).await.is_none() {
+        // A concurrent request can insert the same RSVP first, in which case
+        // the unique key rejects this row and it is updated below instead.
+        let inserted = insert_row(
+            "calendar_rsvps",
+            vec!["event_id", "user_id", "date", "status", "updated"],
+            vec![&event_id.to_string(), user_id, date, status, &now],
+        )
+        .await;
+        if inserted.is_ok() {
+            return Ok(());
+        }
+    }
+    let id = find_rsvp(event_id, date, user_id)
+        .await
+        .ok_or("Your response could not be saved, please try again.")?
+        .id
+        .to_string();
+    change_row_where("calendar_rsvps", "id", &id, "status", status).await;
+    change_row_where("calendar_rsvps", "id", &id, "updated", &now).await;
+    Ok(())
+}
+
+pub async fn get_attendance(
+    event_id: &str,
+    date: Option<&str>,
+) -> (Vec<Attendee>, AttendanceCounts) {
+    let emails: HashMap<i32, String> = get_all_accounts()
+        .await
+        .into_iter()
+        .map(|account| (account.id, account.email))
+        .collect();
+    let mut counts = AttendanceCounts::default();
+    let mut attendees: Vec<Attendee> = get_rsvps_like("event_id", event_id)
+        .await
+        .into_iter()
+        .filter(|rsvp| rsvp.event_id.to_string() == event_id)
+        .filter(|rsvp| date.is_none_or(|x| rsvp.date == x))
+        .map(|rsvp| Attendee {
+            user_id: rsvp.user_id,
+            email: emails.get(&rsvp.user_id).cloned().unwrap_or_default(),
+            date: rsvp.date,
+            status: rsvp.status,
+            updated: rsvp.updated,
+        })
+        .collect();
+    for attendee in &attendees {
+        match attendee.status.as_str() {
+            "yes" => counts.yes += 1,
+            "no" => counts.no += 1,
+            "maybe" => counts.maybe += 1,
+            _ => {}
+        }
+    }
+    attendees.sort_by(|a, b| (&a.date, &a.email).cmp(&(&b.date, &b.email)));
+    (attendees, counts)
+}
@@ -0,0 +1,137 @@
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::{Aead, NewAead};
+use aes_gcm::Aes256Gcm;
+use mysql::*;
+use rand::{thread_rng, Rng};
+
+use crate::accounts::*;
+use crate::config::*;
+
+const ENCRYPTED_PREFIX: &str = "enc:";
+
+fn cipher(key: &str) -> Result<Aes256Gcm, String> {
+    let key = base64::decode(key).map_err(|e| e.to_string())?;
+    if key.len() != 32 {
+        return Err("Token encryption keys must be 32 bytes encoded as base64.".to_string());
+    }
+    Ok(Aes256Gcm::new(GenericArray::from_slice(&key)))
+}
+
+fn encrypt_with(key: &str, plaintext: &str) -> Result<String, String> {
+    let mut nonce = [0u8; 12];
+    thread_rng().fill(&mut nonce);
+    let mut data = nonce.to_vec();
+    data.extend(
+        cipher(key)?
+            .encrypt(GenericArray::from_slice(&nonce), plaintext.as_bytes())
+            .map_err(|_| "The token could not be encrypted.".to_string())?,
+    );
+    Ok(format!("{}{}", ENCRYPTED_PREFIX, base64::encode(data)))
+}
+
+fn decrypt_with(key: &str, ciphertext: &str) -> Result<String, String> {
+    let data = base64::decode(ciphertext).map_err(|e| e.to_string())?;
+    if data.len() < 12 {
+        return Err("The stored token is too short to be valid.".to_string());
+    }
+    let (nonce, data) = data.split_at(12);
+    let plaintext = cipher(key)?
+        .decrypt(GenericArray::from_slice(nonce), data)
+        .map_err(|_| {
+            "The stored token could not be decrypted with the configured key.".to_string()
+        })?;
+    String::from_utf8(plaintext).map_err(|e| e.to_string())
+}
+
+pub fn encrypt_token(plaintext: &str) -> Result<String, String> {
+    encrypt_with(&token_key()?, plaintext)
+}
+
+fn is_encrypted(stored: &str) -> bool {
+    stored.starts_with(ENCRYPTED_PREFIX)
+}
+
+// Tokens stored before encryption was added have no prefix. They are still
+// read as plaintext, and encrypt_plaintext_tokens encrypts them on startup.
+pub fn decrypt_token(stored: &str) -> Result<String, String> {
+    let ciphertext = match stored.strip_prefix(ENCRYPTED_PREFIX) {
+        Some(t) => t,
+        None => return Ok(stored.to_string()),
+    };
+    decrypt_with(&token_key()?, ciphertext).or_else(|e| match previous_token_key() {
+        Some(key) => decrypt_with(&key, ciphertext),
+        None => Err(e),
+    })
+}
+
+async fn reencrypt_tokens(all: bool) -> Result<usize, String> {
+    if all {
+        token_key()?;
+    }
+    let mut count = 0;
+    for account in get_all_accounts().await {
+        if account.refresh_token.is_empty() || !all && is_encrypted(&account.refresh_token) {
+            continue;
+        }
+        let token = decrypt_token(&account.refresh_token)
+            .map_err(|e| format!("Account {}: {}", account.id, e))?;
+        change_row_where(
+            "accounts",
+            "id",
+            &account.id.to_string(),
+            "refresh_token",
+            &encrypt_token(&token)?,
+        )
+        .await;
+        count += 1;
+    }
+    Ok(count)
+}
+
+pub async fn rotate_token_key() -> Result<usize, String> {
+    reencrypt_tokens(true).await
+}
+
+pub async fn encrypt_plaintext_tokens() -> Result<usize, String> {
+    reencrypt_tokens(false).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    const KEY: &str = "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=";
+    const OLD_KEY: &str = "ZmVkY2JhOTg3NjU0MzIxMGZlZGNiYTk4NzY1NDMyMTA=";
+
+    #[test]
+    fn tokens_round_trip() {
+        let stored = encrypt_with(KEY, "refresh-token").unwrap();
+        assert!(is_encrypted(&stored));
+        assert!(!stored.contains("refresh-token"));
+        let ciphertext = stored.strip_prefix(ENCRYPTED_PREFIX).unwrap();
+        assert_eq!(decrypt_with(KEY, ciphertext).unwrap(), "refresh-token");
+        assert!(decrypt_with(OLD_KEY, ciphertext).is_err());
+    }
+
+    #[test]
+    fn tokens_survive_key_rotation() {
+        env::set_var("OLMMCC_TOKEN_KEY", KEY);
+        env::set_var("OLMMCC_PREVIOUS_TOKEN_KEY", OLD_KEY);
+        let old = encrypt_with(OLD_KEY, "refresh-token").unwrap();
+        assert_eq!(decrypt_token(&old).unwrap(), "refresh-token");
+        let rotated = encrypt_token(&decrypt_token(&old).unwrap()).unwrap();
+        assert_ne!(rotated, old);
+        env::remove_var("OLMMCC_PREVIOUS_TOKEN_KEY");
+        assert_eq!(decrypt_token(&rotated).unwrap(), "refresh-token");
+        assert!(decrypt_token(&old).is_err());
+        assert_eq!(decrypt_token("plaintext").unwrap(), "plaintext");
+    }
+
+    #[test]
+    fn keys_must_be_32_bytes() {
+        assert!(encrypt_with("c2hvcnQ=", "refresh-token").is_err());
+        assert!(encrypt_with("not base64!", "refresh-token").is_err());
+    }
+}
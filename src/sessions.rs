@@ -0,0 +1,190 @@
+use mysql::*;
+use serde::Serialize;
+use session::Session;
+
+use crate::config::*;
+use crate::{unix_time, Client};
+
+#[derive(Serialize)]
+pub struct ActiveSession {
+    #[serde(skip)]
+    session: String,
+    #[serde(skip)]
+    user_id: String,
+    id: i64,
+    created: i64,
+    last_seen: i64,
+    user_agent: String,
+    ip: String,
+    remember: bool,
+    expires: i64,
+    max_expires: i64,
+    current: bool,
+}
+
+#[derive(Serialize)]
+pub struct SessionExpiry {
+    expires: i64,
+    max_expires: i64,
+}
+
+pub async fn track_session(session: &mut Session, client: &Client) -> SessionExpiry {
+    let now = unix_time();
+    let remember = session.get("remember").await.unwrap_or_default() == "1";
+    let expiry = SessionExpiry {
+        expires: now + session_idle_lifetime(remember),
+        max_expires: now + session_max_lifetime(remember),
+    };
+    insert_row(
+        "sessions",
+        vec![
+            "session",
+            "user_id",
+            "created",
+            "last_seen",
+            "user_agent",
+            "ip",
+            "remember",
+            "expires",
+            "max_expires",
+        ],
+        vec![
+            &session.get_id(),
+            &session.get("id").await.unwrap(),
+            &now.to_string(),
+            &now.to_string(),
+            &client.user_agent,
+            &client.ip,
+            if remember { "1" } else { "0" },
+            &expiry.expires.to_string(),
+            &expiry.max_expires.to_string(),
+        ],
+    )
+    .await
+    .unwrap();
+    expiry
+}
+
+async fn get_sessions_like(key: &str, value: &str) -> Vec<ActiveSession> {
+    get_like("sessions", key, value)
+        .await
+        .iter()
+        .map(|row| ActiveSession {
+            session: from_value(row[1].clone()),
+            user_id: from_value::<i32>(row[2].clone()).to_string(),
+            id: from_value(row[0].clone()),
+            created: from_value(row[3].clone()),
+            last_seen: from_value(row[4].clone()),
+            user_agent: from_value(row[5].clone()),
+            ip: from_value(row[6].clone()),
+            remember: from_value::<i32>(row[7].clone()) == 1,
+            expires: from_value(row[8].clone()),
+            max_expires: from_value(row[9].clone()),
+            current: false,
+        })
+        .collect()
+}
+
+async fn get_tracked_session(session_id: &str) -> Option<ActiveSession> {
+    get_sessions_like("session", session_id)
+        .await
+        .into_iter()
+        .find(|active| active.session == session_id)
+}
+
+pub async fn get_session_expiry(session_id: &str) -> Option<SessionExpiry> {
+    get_tracked_session(session_id)
+        .await
+        .map(|active| SessionExpiry {
+            expires: active.expires,
+            max_expires: active.max_expires,
+        })
+}
+
+pub async fn touch_session(session_id: &str) -> Option<SessionExpiry> {
+    let active = match get_tracked_session(session_id).await {
+        Some(t) => t,
+        None => {
+            if let Some(mut session) = Session::from_id(session_id).await {
+                if session.get("verified").await.unwrap_or_default() == "1"
+                    || session.get("admin").await.unwrap_or_default() == "1"
+                {
+                    session.delete().await;
+                }
+            }
+            return None;
+        }
+    };
+    let now = unix_time();
+    if now >= active.expires || now >= active.max_expires {
+        revoke_session(session_id).await;
+        return None;
+    }
+    let expires = (now + session_idle_lifetime(active.remember)).min(active.max_expires);
+    change_row_where(
+        "sessions",
+        "session",
+        session_id,
+        "last_seen",
+        &now.to_string(),
+    )
+    .await;
+    change_row_where(
+        "sessions",
+        "session",
+        session_id,
+        "expires",
+        &expires.to_string(),
+    )
+    .await;
+    Some(SessionExpiry {
+        expires,
+        max_expires: active.max_expires,
+    })
+}
+
+async fn get_account_sessions(user_id: &str) -> Vec<ActiveSession> {
+    get_sessions_like("user_id", user_id)
+        .await
+        .into_iter()
+        .filter(|active| active.user_id == user_id)
+        .collect()
+}
+
+pub async fn list_sessions(user_id: &str, current: &str) -> Vec<ActiveSession> {
+    let mut sessions = Vec::new();
+    for mut active in get_account_sessions(user_id).await {
+        if Session::from_id(&active.session).await.is_none() || unix_time() >= active.expires {
+            revoke_session(&active.session).await;
+            continue;
+        }
+        active.current = active.session == current;
+        sessions.push(active);
+    }
+    sessions
+}
+
+pub async fn revoke_session(session_id: &str) {
+    if let Some(mut session) = Session::from_id(session_id).await {
+        session.delete().await;
+    }
+    delete_row_where("sessions", "session", session_id).await;
+}
+
+pub async fn revoke_session_by_id(id: &str, user_id: &str) -> bool {
+    for active in get_account_sessions(user_id).await {
+        if active.id.to_string() == id {
+            revoke_session(&active.session).await;
+            return true;
+        }
+    }
+    false
+}
+
+pub async fn revoke_account_sessions(user_id: &str, keep: Option<&str>) {
+    for active in get_account_sessions(user_id).await {
+        if Some(active.session.as_str()) != keep {
+            revoke_session(&active.session).await;
+        }
+    }
+}
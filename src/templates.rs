@@ -0,0 +1,185 @@
+use mysql::*;
+use serde::Serialize;
+
+use crate::config::*;
+use crate::mailer::Email;
+
+#[derive(Clone, Serialize)]
+pub struct EmailTemplate {
+    name: String,
+    subject: String,
+    text: String,
+    html: String,
+    customized: bool,
+}
+
+const DEFAULT_TEMPLATES: &[(&str, &str, &str, &str)] = &[
+    (
+        "layout",
+        "{{subject}}",
+        "Hello,\r\n{{content}}\r\n\r\nThis message was sent by the OLMMCC automated system. If you received it in error please contact {{contact_email}}",
+        "<html><body><p>Hello,</p>{{content}}<hr><p><small>This message was sent by the OLMMCC automated system. If you received it in error please contact <a href=\"mailto:{{contact_email}}\">{{contact_email}}</a></small></p></body></html>",
+    ),
+    (
+        "login",
+        "Verify Your Identity",
+        "To verify your identity, please copy this code and return to OLMMCC's website: {{code}}",
+        "<p>To verify your identity, please copy this code and return to OLMMCC's website:</p><p><strong>{{code}}</strong></p>",
+    ),
+    (
+        "change_email",
+        "Verify your Email Change Request",
+        "You requested a change of your email address to {{new_email}}. Please copy this code and return to OLMMCC's website: {{code}}",
+        "<p>You requested a change of your email address to {{new_email}}. Please copy this code and return to OLMMCC's website:</p><p><strong>{{code}}</strong></p>",
+    ),
+    (
+        "delete_account",
+        "Verify your Account Deletion Request",
+        "You requested a deletion of your OLMMCC account. Please copy this code and return to OLMMCC's website: {{code}}",
+        "<p>You requested a deletion of your OLMMCC account. Please copy this code and return to OLMMCC's website:</p><p><strong>{{code}}</strong></p>",
+    ),
+    (
+        "reminder",
+        "Reminder: {{title}} on {{date}}",
+        "This is a reminder that {{title}} is coming up on {{date}} from {{start_time}} to {{end_time}}.\r\n\r\n{{notes}}",
+        "<p>This is a reminder that <strong>{{title}}</strong> is coming up on {{date}} from {{start_time}} to {{end_time}}.</p><p>{{notes}}</p>",
+    ),
+];
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn fill(template: &str, variables: &[(&str, &str)], html: bool) -> String {
+    let mut filled = template.to_string();
+    for (name, value) in variables {
+        let value = if html {
+            escape_html(value)
+        } else {
+            value.to_string()
+        };
+        filled = filled.replace(&format!("{{{{{}}}}}", name), &value);
+    }
+    filled
+}
+
+impl EmailTemplate {
+    pub fn edit(&mut self, subject: Option<&&str>, text: Option<&&str>, html: Option<&&str>) {
+        if let Some(t) = subject {
+            self.subject = t.to_string();
+        }
+        if let Some(t) = text {
+            self.text = t.to_string();
+        }
+        if let Some(t) = html {
+            self.html = t.to_string();
+        }
+    }
+}
+
+pub fn is_template(name: &str) -> bool {
+    DEFAULT_TEMPLATES.iter().any(|x| x.0 == name)
+}
+
+pub async fn get_template(name: &str) -> Option<EmailTemplate> {
+    let default = DEFAULT_TEMPLATES.iter().find(|x| x.0 == name)?;
+    let stored = get_like("email_templates", "name", name)
+        .await
+        .iter()
+        .map(|row| EmailTemplate {
+            name: from_value(row[1].clone()),
+            subject: from_value(row[2].clone()),
+            text: from_value(row[3].clone()),
+            html: from_value(row[4].clone()),
+            customized: true,
+        })
+        .find(|template| template.name == name);
+    Some(stored.unwrap_or_else(|| EmailTemplate {
+        name: default.0.to_string(),
+        subject: default.1.to_string(),
+        text: default.2.to_string(),
+        html: default.3.to_string(),
+        customized: false,
+    }))
+}
+
+pub async fn get_templates() -> Vec<EmailTemplate> {
+    let mut templates = Vec::new();
+    for default in DEFAULT_TEMPLATES {
+        templates.push(get_template(default.0).await.unwrap());
+    }
+    templates
+}
+
+pub async fn save_template(name: &str, subject: &str, text: &str, html: &str) {
+    if row_exists("email_templates", "name", name).await {
+        change_row_where("email_templates", "name", name, "subject", subject).await;
+        change_row_where("email_templates", "name", name, "text", text).await;
+        change_row_where("email_templates", "name", name, "html", html).await;
+    } else {
+        insert_row(
+            "email_templates",
+            vec!["name", "subject", "text", "html"],
+            vec![name, subject, text, html],
+        )
+        .await
+        .unwrap();
+    }
+}
+
+pub async fn reset_template(name: &str) {
+    delete_row_where("email_templates", "name", name).await;
+}
+
+pub async fn render(
+    template: &EmailTemplate,
+    variables: &[(&str, &str)],
+    to: Vec<String>,
+) -> Email {
+    let contact_email = contact_email();
+    let mut variables = variables.to_vec();
+    variables.push(("contact_email", contact_email.as_str()));
+    let subject = fill(&template.subject, &variables, false);
+    let mut text = fill(&template.text, &variables, false);
+    let mut html = fill(&template.html, &variables, true);
+    if template.name != "layout" {
+        let layout = get_template("layout").await.unwrap();
+        let layout_variables = [
+            ("subject", subject.as_str()),
+            ("contact_email", contact_email.as_str()),
+        ];
+        text = fill(&layout.text, &layout_variables, false).replace("{{content}}", &text);
+        html = fill(&layout.html, &layout_variables, true).replace("{{content}}", &html);
+    }
+    let mut email = Email::new(to, &subject, &text);
+    email.html = Some(html);
+    email
+}
+
+pub fn personalize(email: &Email, variables: &[(&str, &str)]) -> Email {
+    let mut email = email.clone();
+    email.subject = fill(&email.subject, variables, false);
+    email.body = fill(&email.body, variables, false);
+    email.html = email.html.map(|x| fill(&x, variables, true));
+    email
+}
+
+pub fn is_personalized(email: &Email, variables: &[&str]) -> bool {
+    let content = format!(
+        "{}{}{}",
+        email.subject,
+        email.body,
+        email.html.as_deref().unwrap_or_default()
+    );
+    variables
+        .iter()
+        .any(|name| content.contains(&format!("{{{{{}}}}}", name)))
+}
+
+pub async fn render_template(name: &str, variables: &[(&str, &str)], to: Vec<String>) -> Email {
+    render(&get_template(name).await.unwrap(), variables, to).await
+}
@@ -0,0 +1,91 @@
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+
+use crate::config::*;
+use crate::mailer::Email;
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn signing_mac(purpose: &str, id: &str) -> Result<HmacSha256, String> {
+    let mut mac =
+        HmacSha256::new_varkey(unsubscribe_secret()?.as_bytes()).map_err(|e| e.to_string())?;
+    mac.update(purpose.as_bytes());
+    mac.update(b":");
+    mac.update(id.as_bytes());
+    Ok(mac)
+}
+
+pub fn signed_token(purpose: &str, id: &str) -> Result<String, String> {
+    Ok(base64::encode_config(
+        signing_mac(purpose, id)?.finalize().into_bytes(),
+        base64::URL_SAFE_NO_PAD,
+    ))
+}
+
+pub fn verify_signed_token(purpose: &str, id: &str, token: &str) -> bool {
+    match (
+        base64::decode_config(token, base64::URL_SAFE_NO_PAD),
+        signing_mac(purpose, id),
+    ) {
+        (Ok(t), Ok(mac)) => mac.verify(&t).is_ok(),
+        _ => false,
+    }
+}
+
+pub fn unsubscribe_token(id: &str) -> Result<String, String> {
+    signed_token("unsubscribe", id)
+}
+
+pub fn verify_unsubscribe_token(id: &str, token: &str) -> bool {
+    verify_signed_token("unsubscribe", id, token)
+}
+
+pub fn unsubscribe_link(id: &str) -> Result<String, String> {
+    Ok(format!(
+        "{}/unsubscribe/?id={}&token={}",
+        site_url(),
+        id,
+        unsubscribe_token(id)?
+    ))
+}
+
+fn add_footer(email: &mut Email, link: &str) {
+    email.body.push_str(&format!(
+        "\r\n\r\nTo stop receiving these emails, unsubscribe here: {}",
+        link
+    ));
+    if let Some(html) = &mut email.html {
+        let footer = format!(
+            "<p><small><a href=\"{}\">Unsubscribe from these emails</a></small></p>",
+            link
+        );
+        match html.rfind("</body>") {
+            Some(i) => html.insert_str(i, &footer),
+            None => html.push_str(&footer),
+        }
+    }
+}
+
+pub fn add_unsubscribe(email: &mut Email, id: &str) -> Result<(), String> {
+    let token = unsubscribe_token(id)?;
+    add_footer(email, &unsubscribe_link(id)?);
+    email.headers.push((
+        "List-Unsubscribe".to_string(),
+        format!("<{}/unsubscribe?id={}&token={}>", api_url(), id, token),
+    ));
+    email.headers.push((
+        "List-Unsubscribe-Post".to_string(),
+        "List-Unsubscribe=One-Click".to_string(),
+    ));
+    Ok(())
+}
+
+// A BCC batch can't say who is unsubscribing, so it asks by email instead of
+// offering a one-click link.
+pub fn add_bulk_unsubscribe(email: &mut Email) {
+    let link = format!("mailto:{}?subject=Unsubscribe", contact_email());
+    add_footer(email, &link);
+    email
+        .headers
+        .push(("List-Unsubscribe".to_string(), format!("<{}>", link)));
+}